@@ -33,8 +33,7 @@ impl<T> BloomFilter<T> {
     pub fn new(planned_capacity: usize, false_positives_probability: f64) -> Self {
         let planned_capacity = planned_capacity as f64;
 
-        let bits =
-            (-1.0 * planned_capacity * false_positives_probability.ln()) / 2_f64.ln().powf(2.0);
+        let bits = (-planned_capacity * false_positives_probability.ln()) / 2_f64.ln().powf(2.0);
 
         let hash_functions = (bits / planned_capacity * 2_f64.ln()).ceil() as usize;
 
@@ -44,6 +43,20 @@ impl<T> BloomFilter<T> {
             _phantom: Default::default(),
         }
     }
+
+    /// Creates a new filter which spends a fixed number of bits on every planned element, the
+    /// number of hash functions is chosen to minimize false positives for that size.
+    pub fn with_bits_per_key(planned_capacity: usize, bits_per_key: usize) -> Self {
+        let bits = (planned_capacity * bits_per_key).max(1);
+
+        let hash_functions = ((bits_per_key as f64 * 2_f64.ln()).round() as usize).max(1);
+
+        Self {
+            filter: BitMap::new(bits),
+            hash_functions,
+            _phantom: Default::default(),
+        }
+    }
 }
 
 impl<T> BloomFilter<T>
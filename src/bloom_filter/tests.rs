@@ -24,6 +24,19 @@ fn test_filter_size() {
     assert_eq!(filter.hash_functions, 4);
 }
 
+#[test]
+fn test_filter_size_with_bits_per_key() {
+    let filter = BloomFilter::<u64>::with_bits_per_key(10000, 10);
+
+    assert_eq!(filter.filter.bit_size(), 100000);
+    assert_eq!(filter.hash_functions, 7);
+
+    let filter = BloomFilter::<u64>::with_bits_per_key(0, 10);
+
+    assert_eq!(filter.filter.bit_size(), 1);
+    assert!(!filter.contains(&1));
+}
+
 #[test]
 fn test_addition_and_finding() {
     let false_positives_probability = 0.1;
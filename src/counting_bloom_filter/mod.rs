@@ -30,7 +30,7 @@ impl<T> CountingBloomFilter<T> {
     ) -> Self {
         let planned_capacity = planned_capacity as f64;
 
-        let counters = ((-1.0 * planned_capacity * false_positives_probability.ln())
+        let counters = ((-planned_capacity * false_positives_probability.ln())
             / 2_f64.ln().powf(2.0)) as usize;
 
         let hash_functions = (counters as f64 / planned_capacity * 2_f64.ln()).ceil() as usize;
@@ -1,5 +1,7 @@
 pub mod bit_map;
 pub mod bloom_filter;
 pub mod counting_bloom_filter;
+pub mod lru_cache;
 pub mod lsm_tree;
 pub mod sstable;
+pub mod wal;
@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// Map of a limited capacity which evicts the least recently used entries when a new one doesn't
+/// fit.
+///
+/// Every entry has a charge, the part of the capacity it takes. Entries inserted with
+/// [`LruCache::insert`] are charged one, so the capacity is a number of entries, while
+/// [`LruCache::insert_with_charge`] allows to limit e.g. the total size of the values in bytes.
+///
+/// Both reading and inserting an entry mark it as the most recently used one. The cache counts
+/// lookups which found their entry (hits) and those which didn't (misses).
+pub struct LruCache<K, V> {
+    capacity: usize,
+    usage: usize,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys of all entries by the moment they were used last, from the least recently used.
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    charge: usize,
+    used: u64,
+}
+
+impl<K, V> LruCache<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates an empty cache whose entries may be charged `capacity` in total.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+
+        self.hits += 1;
+
+        let key = self.recency.remove(&entry.used).unwrap();
+        self.clock += 1;
+        entry.used = self.clock;
+        self.recency.insert(self.clock, key);
+
+        Some(&entry.value)
+    }
+
+    /// Inserts an entry charged one, see [`LruCache::insert_with_charge`].
+    pub fn insert(&mut self, key: K, value: V) {
+        self.insert_with_charge(key, value, 1);
+    }
+
+    /// Inserts an entry, replacing the previous value of the key, and evicts the least recently
+    /// used entries until the new one fits. An entry charged more than the whole capacity is not
+    /// inserted at all.
+    pub fn insert_with_charge(&mut self, key: K, value: V, charge: usize) {
+        self.remove(&key);
+
+        if charge > self.capacity {
+            return;
+        }
+
+        while self.usage + charge > self.capacity {
+            let (_, evicted) = self.recency.pop_first().unwrap();
+            self.usage -= self.entries.remove(&evicted).unwrap().charge;
+        }
+
+        self.clock += 1;
+        self.usage += charge;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                charge,
+                used: self.clock,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.used);
+        self.usage -= entry.charge;
+
+        Some(entry.value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, evicting the least recently used entries until the rest fits.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        while self.usage > self.capacity {
+            let (_, evicted) = self.recency.pop_first().unwrap();
+            self.usage -= self.entries.remove(&evicted).unwrap().charge;
+        }
+    }
+
+    /// Total charge of all entries in the cache.
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    /// Number of lookups which found their entry.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of lookups which didn't find their entry.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
@@ -0,0 +1,127 @@
+use crate::lru_cache::LruCache;
+
+#[test]
+fn test_insert_and_get() {
+    let mut cache = LruCache::new(2);
+
+    cache.insert(1, "one");
+    cache.insert(2, "two");
+
+    assert_eq!(cache.get(&1), Some(&"one"));
+    assert_eq!(cache.get(&2), Some(&"two"));
+    assert_eq!(cache.get(&3), None);
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_least_recently_used_entry_is_evicted() {
+    let mut cache = LruCache::new(2);
+
+    cache.insert(1, "one");
+    cache.insert(2, "two");
+
+    // Reading the first entry makes the second one the least recently used.
+    cache.get(&1);
+    cache.insert(3, "three");
+
+    assert_eq!(cache.get(&1), Some(&"one"));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&3), Some(&"three"));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_insert_replaces_value_without_eviction() {
+    let mut cache = LruCache::new(2);
+
+    cache.insert(1, "one");
+    cache.insert(2, "two");
+    cache.insert(1, "uno");
+
+    assert_eq!(cache.get(&1), Some(&"uno"));
+    assert_eq!(cache.get(&2), Some(&"two"));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_remove() {
+    let mut cache = LruCache::new(2);
+
+    cache.insert(1, "one");
+
+    assert_eq!(cache.remove(&1), Some("one"));
+    assert_eq!(cache.remove(&1), None);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_zero_capacity_holds_nothing() {
+    let mut cache = LruCache::new(0);
+
+    cache.insert(1, "one");
+
+    assert_eq!(cache.get(&1), None);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_hits_and_misses_are_counted() {
+    let mut cache = LruCache::new(2);
+
+    cache.insert(1, "one");
+    cache.get(&1);
+    cache.get(&1);
+    cache.get(&2);
+
+    assert_eq!(cache.hits(), 2);
+    assert_eq!(cache.misses(), 1);
+}
+
+#[test]
+fn test_entries_are_evicted_by_charge() {
+    let mut cache = LruCache::new(10);
+
+    cache.insert_with_charge(1, "one", 4);
+    cache.insert_with_charge(2, "two", 4);
+    assert_eq!(cache.usage(), 8);
+
+    // Both older entries have to go to make room for the new one.
+    cache.insert_with_charge(3, "three", 8);
+
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&3), Some(&"three"));
+    assert_eq!(cache.usage(), 8);
+
+    cache.remove(&3);
+    assert_eq!(cache.usage(), 0);
+}
+
+#[test]
+fn test_entry_bigger_than_capacity_is_not_inserted() {
+    let mut cache = LruCache::new(10);
+
+    cache.insert_with_charge(1, "one", 4);
+    cache.insert_with_charge(2, "two", 11);
+
+    assert_eq!(cache.get(&1), Some(&"one"));
+    assert_eq!(cache.get(&2), None);
+}
+
+#[test]
+fn test_shrinking_capacity_evicts_least_recently_used_entries() {
+    let mut cache = LruCache::new(10);
+
+    cache.insert_with_charge(1, "one", 4);
+    cache.insert_with_charge(2, "two", 4);
+    cache.get(&1);
+
+    cache.set_capacity(5);
+    assert_eq!(cache.get(&1), Some(&"one"));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.usage(), 4);
+
+    cache.set_capacity(10);
+    cache.insert_with_charge(2, "two", 4);
+    assert_eq!(cache.usage(), 8);
+}
@@ -0,0 +1,19 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the time an [`LsmTree`](crate::lsm_tree::LsmTree) checks entries with a time to
+/// live against.
+pub trait Clock: Send + Sync {
+    /// Current time in milliseconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// Clock of the operating system, used by trees unless another one is set.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
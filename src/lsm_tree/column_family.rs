@@ -0,0 +1,172 @@
+use crate::lsm_tree::{Changes, LsmTree, Pair, RangeIter, Value, Writer};
+use std::{
+    error::Error,
+    hash::Hash,
+    ops::{Bound, Range, RangeBounds},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+/// Handle of a column family of an [`LsmTree`]: a keyspace with its own memtables, SsTables and
+/// tuning.
+///
+/// Families share the WAL and the manifest of the tree, so a
+/// [`WriteBatch`](crate::lsm_tree::WriteBatch) may change several of them at once, and a
+/// [`Snapshot`](crate::lsm_tree::Snapshot) sees all of them as of the same write.
+pub struct ColumnFamily<'a, K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    tree: &'a LsmTree<K, V>,
+    id: usize,
+}
+
+impl<'a, K, V> ColumnFamily<'a, K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    pub(super) fn new(tree: &'a LsmTree<K, V>, id: usize) -> Self {
+        Self { tree, id }
+    }
+
+    pub(super) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> String {
+        let state = self.tree.inner.state.lock().unwrap();
+        state.families[self.id].name.clone()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<(), Box<dyn Error>> {
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+        self.write(&mut writer, vec![(key, Value::Data(value))], Vec::new())
+    }
+
+    /// Inserts a pair which reads as deleted once `ttl` has passed. Compaction drops it for good
+    /// after that.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Result<(), Box<dyn Error>> {
+        let expires_at = self
+            .tree
+            .inner
+            .now()
+            .saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX));
+
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+        let value = Value::Expiring {
+            data: value,
+            expires_at,
+        };
+        self.write(&mut writer, vec![(key, value)], Vec::new())
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
+        // Writes in progress are left out, as they may be only partly in the memtable.
+        let sequence = self.tree.inner.last_sequence.load(Ordering::SeqCst);
+        self.tree.inner.get(self.id, key, sequence)
+    }
+
+    /// Returns an ordered iterator over all live pairs of the family whose keys are in `range`.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<RangeIter<K, V>, Box<dyn Error>> {
+        let sequence = self.tree.inner.last_sequence.load(Ordering::SeqCst);
+        self.tree.inner.range(self.id, range, sequence)
+    }
+
+    /// Returns an ordered iterator over all live pairs of the family whose keys start with
+    /// `prefix`.
+    pub fn prefix(
+        &self,
+        prefix: K,
+    ) -> Result<impl Iterator<Item = Pair<K, V>> + use<K, V>, Box<dyn Error>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let iter = self.range((Bound::Included(prefix.clone()), Bound::Unbounded))?;
+
+        Ok(iter.take_while(move |pair| match pair {
+            Ok((key, _)) => key.as_ref().starts_with(prefix.as_ref()),
+            Err(_) => true,
+        }))
+    }
+
+    pub fn delete(&self, key: K) -> Result<Option<V>, Box<dyn Error>> {
+        // Holding the writer makes sure the returned value is the one the tombstone replaces.
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+
+        let value = self.tree.inner.get(self.id, &key, u64::MAX)?;
+        if value.is_none() {
+            return Ok(None);
+        };
+
+        self.write(&mut writer, vec![(key, Value::Tombstone)], Vec::new())?;
+        Ok(value)
+    }
+
+    /// Deletes `key` without looking it up first, so it never reads SsTables.
+    pub fn remove(&self, key: K) -> Result<(), Box<dyn Error>> {
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+        self.write(&mut writer, vec![(key, Value::Tombstone)], Vec::new())
+    }
+
+    /// Deletes `key` and returns whether it existed, writing no tombstone when it didn't.
+    pub fn remove_checked(&self, key: K) -> Result<bool, Box<dyn Error>> {
+        // Holding the writer makes sure the answer is about the version the tombstone replaces.
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+
+        // Lookups skip SsTables whose bloom filters rule the key out.
+        if self.tree.inner.get(self.id, &key, u64::MAX)?.is_none() {
+            return Ok(false);
+        }
+
+        self.write(&mut writer, vec![(key, Value::Tombstone)], Vec::new())?;
+        Ok(true)
+    }
+
+    /// Writes `operand` to be applied to the value of `key` by the merge operator of the tree.
+    pub fn merge(&self, key: K, operand: V) -> Result<(), Box<dyn Error>> {
+        if self.tree.inner.merge_operator().is_none() {
+            return Err("LSM tree has no merge operator".into());
+        }
+
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+        self.write(&mut writer, vec![(key, Value::Merge(operand))], Vec::new())
+    }
+
+    /// Deletes all keys of the family in `range` with a single range tombstone.
+    pub fn delete_range(&self, range: Range<K>) -> Result<(), Box<dyn Error>> {
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let mut writer = self.tree.inner.writer.lock().unwrap();
+        self.write(&mut writer, Vec::new(), vec![(range.start, range.end)])
+    }
+
+    /// Makes the active memtable of the family immutable and waits until all its memtables are
+    /// written into level0 SsTables.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        self.tree.inner.flush(&[self.id])
+    }
+
+    /// Compacts the levels of the family, like [`LsmTree::compact`] does for all families.
+    pub fn compact(&self) -> Result<(), Box<dyn Error>> {
+        self.tree.inner.compact(self.id)
+    }
+
+    fn write(
+        &self,
+        writer: &mut Writer<K, V>,
+        pairs: Vec<(K, Value<V>)>,
+        deleted_ranges: Vec<(K, K)>,
+    ) -> Result<(), Box<dyn Error>> {
+        let changes = Changes {
+            family: self.id,
+            pairs,
+            deleted_ranges,
+        };
+
+        self.tree.inner.write(writer, vec![changes])
+    }
+}
@@ -0,0 +1,244 @@
+use crate::lsm_tree::{MergeOperator, Value};
+use std::error::Error;
+
+/// Versions of a key kept by the tree, from the newest to the oldest, each stamped with the
+/// sequence number of the write which made it.
+#[derive(bincode::Encode, bincode::Decode, Clone)]
+pub(super) struct History<V>
+where
+    V: Clone,
+{
+    versions: Vec<(u64, Value<V>)>,
+}
+
+impl<V> History<V>
+where
+    V: Clone,
+{
+    pub(super) fn new(sequence: u64, value: Value<V>) -> Self {
+        Self {
+            versions: vec![(sequence, value)],
+        }
+    }
+
+    /// Versions of the key written up to `sequence`, from the newest one, with the sequence
+    /// numbers of their writes.
+    pub(super) fn visible(&self, sequence: u64) -> impl Iterator<Item = (u64, &Value<V>)> {
+        self.versions
+            .iter()
+            .filter(move |(version, _)| *version <= sequence)
+            .map(|(version, value)| (*version, value))
+    }
+
+    pub(super) fn push_newest(&mut self, sequence: u64, value: Value<V>) {
+        self.versions.insert(0, (sequence, value));
+    }
+
+    /// Adds versions from a source older than this one.
+    pub(super) fn append_older(&mut self, older: History<V>) {
+        self.versions.extend(older.versions);
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub(super) fn first_sequence(&self) -> u64 {
+        self.versions.last().map_or(0, |(sequence, _)| *sequence)
+    }
+
+    pub(super) fn last_sequence(&self) -> u64 {
+        self.versions.first().map_or(0, |(sequence, _)| *sequence)
+    }
+
+    /// Drops versions nobody can read anymore: only the newest version and the versions seen by
+    /// the snapshots at `snapshots` sequences are kept, together with everything merge operands
+    /// among them are applied to. A version is not seen by a reader which also sees a range
+    /// tombstone of the key written after it, so `deleted_at` lists the sequence numbers of the
+    /// range tombstones covering the key.
+    pub(super) fn retain_visible(&mut self, snapshots: &[u64], deleted_at: &[u64]) {
+        let mut visible = vec![false; self.versions.len()];
+
+        let readers = snapshots.iter().copied().chain([u64::MAX]);
+
+        for sequence in readers {
+            for (i, (version, value)) in self.versions.iter().enumerate() {
+                if *version > sequence {
+                    continue;
+                }
+
+                if deleted_at
+                    .iter()
+                    .any(|deleted| version < deleted && *deleted <= sequence)
+                {
+                    break;
+                }
+
+                visible[i] = true;
+
+                if !matches!(value, Value::Merge(_)) {
+                    break;
+                }
+            }
+        }
+
+        let mut visible = visible.into_iter();
+        self.versions.retain(|_| visible.next().unwrap());
+    }
+
+    /// Turns versions which expired by `now` into tombstones, which is what every reader sees of
+    /// them.
+    pub(super) fn expire(&mut self, now: u64) {
+        for (_, value) in &mut self.versions {
+            if value.expired(now) {
+                *value = Value::Tombstone;
+            }
+        }
+    }
+
+    /// Replaces the merge operands on top of the history and the value below them with a single
+    /// value. `base_known` tells if the key has no versions older than the history, for when the
+    /// history holds only operands. Only safe when no snapshot may see the versions in between.
+    /// Operands on top of a value with a time to live are left until it expires, as they apply to
+    /// nothing afterwards.
+    pub(super) fn fold_operands<K>(
+        &mut self,
+        key: &K,
+        operator: &dyn MergeOperator<K, V>,
+        base_known: bool,
+    ) {
+        let operands = self
+            .versions
+            .iter()
+            .take_while(|(_, value)| matches!(value, Value::Merge(_)))
+            .count();
+
+        if operands == 0 {
+            return;
+        }
+
+        let base = match self.versions.get(operands) {
+            Some((_, Value::Data(value))) => Some(value),
+            Some((_, Value::Expiring { .. })) => return,
+            Some(_) => None,
+            None if base_known => None,
+            None => return,
+        };
+
+        let operand_values: Vec<V> = self.versions[..operands]
+            .iter()
+            .rev()
+            .filter_map(|(_, value)| match value {
+                Value::Merge(operand) => Some(operand.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let merged = operator.merge(key, base, &operand_values);
+        self.versions = vec![(self.versions[0].0, Value::Data(merged))];
+    }
+
+    /// Drops the oldest version if it's a tombstone and returns whether it did. Only safe when no
+    /// older version of the key exists anywhere else.
+    pub(super) fn pop_oldest_tombstone(&mut self) -> bool {
+        if matches!(self.versions.last(), Some((_, Value::Tombstone))) {
+            self.versions.pop();
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Collects what a reader sees of a key from its sources, from the newest one, until a value or a
+/// tombstone settles the key. Merge operands met on the way are applied to that value, and values
+/// which expired count as tombstones.
+pub(super) struct Lookup<V> {
+    sequence: u64,
+    /// Time of the read, in milliseconds since the Unix epoch.
+    now: u64,
+    /// Operands from the newest to the oldest.
+    operands: Vec<V>,
+    /// `Some` once the key is settled.
+    base: Option<Option<V>>,
+}
+
+impl<V> Lookup<V>
+where
+    V: Clone,
+{
+    /// Starts a lookup as a reader of the writes up to `sequence` at the time `now`.
+    pub(super) fn new(sequence: u64, now: u64) -> Self {
+        Self {
+            sequence,
+            now,
+            operands: Vec::new(),
+            base: None,
+        }
+    }
+
+    /// Adds the versions of a source older than all sources added so far, along with the sequence
+    /// number of the newest range tombstone of the source which covers the key. Returns whether
+    /// the key is settled, so older sources don't matter.
+    pub(super) fn add(&mut self, history: Option<&History<V>>, deleted_at: Option<u64>) -> bool {
+        let versions = history
+            .into_iter()
+            .flat_map(|history| history.visible(self.sequence));
+
+        for (version, value) in versions {
+            if deleted_at.is_some_and(|deleted_at| version < deleted_at) {
+                break;
+            }
+
+            match value {
+                Value::Data(value) => {
+                    self.base = Some(Some(value.clone()));
+                    return true;
+                }
+                Value::Expiring { .. } if value.expired(self.now) => {
+                    self.base = Some(None);
+                    return true;
+                }
+                Value::Expiring { data, .. } => {
+                    self.base = Some(Some(data.clone()));
+                    return true;
+                }
+                Value::Tombstone => {
+                    self.base = Some(None);
+                    return true;
+                }
+                Value::Merge(operand) => self.operands.push(operand.clone()),
+            }
+        }
+
+        // The range tombstone hides everything older.
+        if deleted_at.is_some() {
+            self.base = Some(None);
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns the value of `key`, applying the collected operands with `operator`.
+    pub(super) fn finish<K>(
+        self,
+        key: &K,
+        operator: Option<&dyn MergeOperator<K, V>>,
+    ) -> Result<Option<V>, Box<dyn Error>> {
+        let base = self.base.flatten();
+
+        if self.operands.is_empty() {
+            return Ok(base);
+        }
+
+        let Some(operator) = operator else {
+            return Err("found merge operands, but the LSM tree has no merge operator".into());
+        };
+
+        let mut operands = self.operands;
+        operands.reverse();
+
+        Ok(Some(operator.merge(key, base.as_ref(), &operands)))
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    lsm_tree::{LsmTreeConfig, State, Step, TableMeta, reach},
+    wal::Wal,
+};
+use std::{error::Error, io::Write};
+
+/// Append-only log of the changes made to the layout of an [`LsmTree`](crate::lsm_tree::LsmTree).
+///
+/// The first edits of a manifest describe the whole tree, one column family each, every later one
+/// a new column family or the tables a flush or a compaction added and removed. The `CURRENT`
+/// file names the manifest in use, so a new manifest takes over at once when `CURRENT` is
+/// replaced.
+pub(super) struct Manifest<K> {
+    wal: Wal<VersionEdit<K>>,
+}
+
+/// Change of the layout of a column family, applied with [`State::apply`].
+#[derive(bincode::Encode, bincode::Decode, Clone)]
+pub(super) struct VersionEdit<K> {
+    pub(super) family: usize,
+    /// Name and tuning of the family, set only by the edit which creates it.
+    pub(super) created_family: Option<(String, LsmTreeConfig)>,
+    pub(super) added_tables: Vec<(usize, TableMeta<K>)>,
+    pub(super) removed_tables: Vec<(usize, u64)>,
+    pub(super) compaction_pointers: Vec<(usize, K)>,
+    pub(super) next_file: Option<u64>,
+    pub(super) oldest_wal: Option<u64>,
+    /// Oldest WALs of other column families with no memtable waiting for a flush, so families
+    /// which are rarely written don't keep old WALs around.
+    pub(super) idle_oldest_wals: Vec<(usize, u64)>,
+    pub(super) last_sequence: Option<u64>,
+}
+
+impl<K> Default for VersionEdit<K> {
+    fn default() -> Self {
+        Self {
+            family: 0,
+            created_family: None,
+            added_tables: Vec::new(),
+            removed_tables: Vec::new(),
+            compaction_pointers: Vec::new(),
+            next_file: None,
+            oldest_wal: None,
+            idle_oldest_wals: Vec::new(),
+            last_sequence: None,
+        }
+    }
+}
+
+impl<K> Manifest<K>
+where
+    K: Clone + Ord + bincode::Encode + bincode::Decode<()>,
+{
+    /// Starts a new manifest numbered `number` which describes `state`, points `CURRENT` at it and
+    /// removes the manifests it replaces.
+    pub(super) fn create(
+        data_directory: &str,
+        number: u64,
+        state: &State<K>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut wal = Wal::create(&manifest_path(data_directory, number))?;
+        for edit in state.to_edits() {
+            wal.append(&edit)?;
+        }
+        wal.sync()?;
+        reach(data_directory, Step::ManifestWritten)?;
+
+        // Renaming is atomic, so `CURRENT` names either the old manifest or the new one.
+        let temp_path = format!("{data_directory}/CURRENT.tmp");
+        let mut temp = std::fs::File::create(&temp_path)?;
+        writeln!(temp, "{}", manifest_name(number))?;
+        temp.sync_all()?;
+        std::fs::rename(temp_path, current_path(data_directory))?;
+        std::fs::File::open(data_directory)?.sync_all()?;
+        reach(data_directory, Step::CurrentSwitched)?;
+
+        for entry in std::fs::read_dir(data_directory)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+
+            if name.starts_with("MANIFEST-") && name != manifest_name(number) {
+                std::fs::remove_file(format!("{data_directory}/{name}"))?;
+            }
+        }
+
+        Ok(Self { wal })
+    }
+
+    /// Replays the manifest `CURRENT` points at.
+    pub(super) fn recover(data_directory: &str) -> Result<State<K>, Box<dyn Error>> {
+        // Trees from before the manifest kept their layout in a `state` file.
+        if !std::fs::exists(current_path(data_directory))?
+            && std::fs::exists(format!("{data_directory}/state"))?
+        {
+            return Err(format!(
+                "unsupported pre-manifest layout of the LSM tree in {data_directory}: it has a \
+                 `state` file but no `CURRENT`"
+            )
+            .into());
+        }
+
+        let name = std::fs::read_to_string(current_path(data_directory))?;
+        let (_, edits) = Wal::<VersionEdit<K>>::open(&format!("{data_directory}/{}", name.trim()))?;
+
+        let mut state = State::new();
+
+        for edit in edits {
+            let valid = match edit.created_family {
+                Some(_) => edit.family == state.families.len(),
+                None => edit.family < state.families.len(),
+            };
+
+            if !valid {
+                return Err("manifest refers to an unknown column family".into());
+            }
+
+            state.apply(edit);
+        }
+
+        if state.families.is_empty() {
+            return Err("manifest doesn't start with the layout of the tree".into());
+        }
+
+        Ok(state)
+    }
+
+    /// Appends `edit` and forces it to the disk.
+    pub(super) fn append(&mut self, edit: &VersionEdit<K>) -> Result<(), Box<dyn Error>> {
+        self.wal.append(edit)?;
+        self.wal.sync()
+    }
+}
+
+/// Parses the number of a file of the tree: an SsTable, a WAL or a manifest.
+pub(super) fn file_number(name: &str) -> Option<u64> {
+    let name = name.strip_prefix("MANIFEST-").unwrap_or(name);
+    let digits = name.split('.').next()?;
+    digits.parse().ok()
+}
+
+fn manifest_name(number: u64) -> String {
+    format!("MANIFEST-{number}")
+}
+
+fn manifest_path(data_directory: &str, number: u64) -> String {
+    format!("{data_directory}/{}", manifest_name(number))
+}
+
+fn current_path(data_directory: &str) -> String {
+    format!("{data_directory}/CURRENT")
+}
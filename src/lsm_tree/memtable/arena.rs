@@ -0,0 +1,62 @@
+use crate::lsm_tree::memtable::skip_list::{Node, NodeAllocator, SkipList};
+use std::sync::Mutex;
+
+/// Number of nodes in the first chunk of an arena. Every next chunk is twice as big, up to
+/// [`MAX_CHUNK`].
+const FIRST_CHUNK: usize = 64;
+
+const MAX_CHUNK: usize = 4096;
+
+/// Memtable whose versions are allocated in chunks of an [`Arena`], so a memtable takes a few big
+/// allocations instead of one per version.
+pub(super) type ArenaMemtable<K, V> = SkipList<K, V, Arena<Node<K, V>>>;
+
+/// Allocator handing out slots of ever bigger chunks. Values are dropped only with the arena, all
+/// at once.
+pub(super) struct Arena<T> {
+    chunks: Mutex<Vec<Vec<T>>>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self {
+            chunks: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<T> Arena<T> {
+    pub(super) fn alloc(&self, value: T) -> *mut T {
+        let mut chunks = self.chunks.lock().unwrap();
+
+        if chunks
+            .last()
+            .is_none_or(|chunk| chunk.len() == chunk.capacity())
+        {
+            let capacity = chunks.last().map_or(FIRST_CHUNK, |chunk| {
+                (chunk.capacity() * 2).clamp(FIRST_CHUNK, MAX_CHUNK)
+            });
+            chunks.push(Vec::with_capacity(capacity));
+        }
+
+        // A chunk is never filled over its capacity, so values pushed into it never move.
+        let chunk = chunks.last_mut().unwrap();
+        chunk.push(value);
+
+        chunk.as_mut_ptr().wrapping_add(chunk.len() - 1)
+    }
+}
+
+impl<K, V> NodeAllocator<K, V> for Arena<Node<K, V>>
+where
+    K: Send,
+    V: Clone + Send,
+{
+    fn allocate(&self, node: Node<K, V>) -> *mut Node<K, V> {
+        self.alloc(node)
+    }
+
+    unsafe fn release(&mut self, _first: *mut Node<K, V>) {
+        // Nodes are dropped along with the chunks.
+    }
+}
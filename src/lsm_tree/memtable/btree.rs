@@ -0,0 +1,51 @@
+use crate::lsm_tree::{Value, history::History, memtable::Memtable};
+use std::{collections::BTreeMap, ops::Bound, sync::RwLock};
+
+/// Memtable keeping the history of every key in a [`BTreeMap`] behind a lock.
+pub(super) struct BTreeMemtable<K, V>
+where
+    V: Clone,
+{
+    map: RwLock<BTreeMap<K, History<V>>>,
+}
+
+impl<K, V> BTreeMemtable<K, V>
+where
+    V: Clone,
+{
+    pub(super) fn new() -> Self {
+        Self {
+            map: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<K, V> Memtable<K, V> for BTreeMemtable<K, V>
+where
+    K: Clone + Ord + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn insert(&self, key: K, sequence: u64, value: Value<V>) {
+        let mut map = self.map.write().unwrap();
+
+        match map.get_mut(&key) {
+            Some(history) => history.push_newest(sequence, value),
+            None => {
+                map.insert(key, History::new(sequence, value));
+            }
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<History<V>> {
+        self.map.read().unwrap().get(key).cloned()
+    }
+
+    fn range(&self, bounds: (Bound<K>, Bound<K>)) -> Vec<(K, History<V>)> {
+        self.map
+            .read()
+            .unwrap()
+            .range(bounds)
+            .map(|(key, history)| (key.clone(), history.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,73 @@
+mod arena;
+mod btree;
+mod skip_list;
+#[cfg(test)]
+mod tests;
+
+use arena::ArenaMemtable;
+use btree::BTreeMemtable;
+use skip_list::SkipListMemtable;
+
+use crate::lsm_tree::{Value, history::History};
+use bincode::enc::write::SizeWriter;
+use std::ops::Bound;
+
+/// Ordered collection of the versions of keys written into a memtable.
+///
+/// Versions are only ever added. The tree serializes writers, but readers may come at any time,
+/// including in the middle of a write.
+pub(super) trait Memtable<K, V>: Send + Sync
+where
+    V: Clone,
+{
+    /// Adds the version of `key` made by the write numbered `sequence`.
+    fn insert(&self, key: K, sequence: u64, value: Value<V>);
+
+    /// Returns all versions of `key`, from the newest one.
+    fn get(&self, key: &K) -> Option<History<V>>;
+
+    /// Copies the histories of the keys in `bounds`, in key order.
+    fn range(&self, bounds: (Bound<K>, Bound<K>)) -> Vec<(K, History<V>)>;
+}
+
+/// Data structure a column family of an [`LsmTree`](crate::lsm_tree::LsmTree) keeps the versions
+/// of its memtables in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, bincode::Encode, bincode::Decode)]
+pub enum MemtableKind {
+    /// Ordered map behind a lock, so readers wait while a write is inserted.
+    #[default]
+    BTree,
+    /// Skip list which readers walk without taking locks. Every version is allocated on its own.
+    SkipList,
+    /// Skip list like [`MemtableKind::SkipList`], with versions allocated in big chunks which are
+    /// freed all at once.
+    Arena,
+}
+
+impl MemtableKind {
+    pub(super) fn create<K, V>(self) -> Box<dyn Memtable<K, V>>
+    where
+        K: Clone + Ord + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        match self {
+            MemtableKind::BTree => Box::new(BTreeMemtable::new()),
+            MemtableKind::SkipList => Box::new(SkipListMemtable::new()),
+            MemtableKind::Arena => Box::new(ArenaMemtable::new()),
+        }
+    }
+}
+
+/// Size of `value` in bytes once it's encoded, which is about what it takes in a WAL record or in
+/// an SsTable.
+pub(super) fn encoded_size<T>(value: &T) -> usize
+where
+    T: bincode::Encode,
+{
+    let mut writer = SizeWriter::default();
+
+    // A value which fails to encode never makes it into the WAL, so it's never counted anyway.
+    let _ = bincode::encode_into_writer(value, &mut writer, bincode::config::standard());
+
+    writer.bytes_written
+}
@@ -0,0 +1,289 @@
+use crate::lsm_tree::{Value, history::History, memtable::Memtable};
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    ptr,
+    sync::{
+        Mutex,
+        atomic::{AtomicPtr, Ordering},
+    },
+};
+
+/// Number of levels of a skip list. A quarter of the nodes of a level make it to the next one, so
+/// lookups stay fast up to millions of versions.
+const MAX_HEIGHT: usize = 12;
+
+/// Memtable whose versions are allocated one by one.
+pub(super) type SkipListMemtable<K, V> = SkipList<K, V, Heap>;
+
+/// Skip list with a node for every version, ordered by key and then from the newest version.
+///
+/// Nodes are never changed or removed once they are linked, so readers walk the list without
+/// locks. A writer links a node only after filling it, from the bottom level up, so a reader finds
+/// either all of it or nothing. Writers take turns.
+pub(super) struct SkipList<K, V, A>
+where
+    V: Clone,
+    A: NodeAllocator<K, V>,
+{
+    /// Links of the head of the list, which holds no version.
+    head: [AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+    allocator: A,
+    /// Serializes writers, and holds the state of the generator of node heights.
+    writer: Mutex<u64>,
+    /// The list owns its nodes, though it only points to them.
+    owns: PhantomData<Box<Node<K, V>>>,
+}
+
+// SAFETY: the list owns the nodes it points to, so it may go to another thread whenever their
+// keys and values may.
+unsafe impl<K, V, A> Send for SkipList<K, V, A>
+where
+    K: Send,
+    V: Clone + Send,
+    A: NodeAllocator<K, V>,
+{
+}
+
+// SAFETY: readers on any thread get shared references to keys and values, and writers on any
+// thread move keys and values in, which are dropped by whichever thread drops the list.
+unsafe impl<K, V, A> Sync for SkipList<K, V, A>
+where
+    K: Send + Sync,
+    V: Clone + Send + Sync,
+    A: NodeAllocator<K, V>,
+{
+}
+
+pub(super) struct Node<K, V>
+where
+    V: Clone,
+{
+    key: K,
+    sequence: u64,
+    value: Value<V>,
+    /// Next node on every level. Only the levels the node was linked to are set.
+    next: [AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+}
+
+/// Gives nodes of a [`SkipList`] memory which stays where it is for as long as the list lives.
+pub(super) trait NodeAllocator<K, V>: Default + Send + Sync
+where
+    V: Clone,
+{
+    fn allocate(&self, node: Node<K, V>) -> *mut Node<K, V>;
+
+    /// Frees all nodes of the list starting at `first`, unless they go away with the allocator.
+    ///
+    /// # Safety
+    ///
+    /// Called just once, by the list being dropped, as no node may be used afterwards.
+    unsafe fn release(&mut self, first: *mut Node<K, V>);
+}
+
+/// Allocates every node on its own and frees them one by one.
+#[derive(Default)]
+pub(super) struct Heap;
+
+impl<K, V> NodeAllocator<K, V> for Heap
+where
+    V: Clone,
+{
+    fn allocate(&self, node: Node<K, V>) -> *mut Node<K, V> {
+        Box::into_raw(Box::new(node))
+    }
+
+    unsafe fn release(&mut self, first: *mut Node<K, V>) {
+        let mut node = first;
+
+        while !node.is_null() {
+            // SAFETY: every node of the list comes from `allocate`, and the bottom level links each
+            // of them just once.
+            let current = unsafe { Box::from_raw(node) };
+            node = current.next[0].load(Ordering::Relaxed);
+        }
+    }
+}
+
+impl<K, V, A> SkipList<K, V, A>
+where
+    K: Ord,
+    V: Clone,
+    A: NodeAllocator<K, V>,
+{
+    pub(super) fn new() -> Self {
+        Self {
+            head: Default::default(),
+            allocator: A::default(),
+            writer: Mutex::new(0x2545_f491_4f6c_dd1d),
+            owns: PhantomData,
+        }
+    }
+
+    /// Links of `node`, or of the head of the list when it's null.
+    fn links(&self, node: *mut Node<K, V>) -> &[AtomicPtr<Node<K, V>>; MAX_HEIGHT] {
+        // SAFETY: nodes are freed only when the list is dropped.
+        match unsafe { node.as_ref() } {
+            Some(node) => &node.next,
+            None => &self.head,
+        }
+    }
+
+    /// Returns the first node which doesn't go before the version of `key` numbered `sequence`,
+    /// or null when there is none. `before` receives the last node before it on every level, or
+    /// null for the head.
+    fn seek(
+        &self,
+        key: &K,
+        sequence: u64,
+        mut before: Option<&mut [*mut Node<K, V>; MAX_HEIGHT]>,
+    ) -> *mut Node<K, V> {
+        let mut node = ptr::null_mut();
+        let mut next = ptr::null_mut();
+
+        for level in (0..MAX_HEIGHT).rev() {
+            loop {
+                next = self.links(node)[level].load(Ordering::Acquire);
+
+                // SAFETY: nodes are freed only when the list is dropped.
+                match unsafe { next.as_ref() } {
+                    Some(candidate) if candidate.precedes(key, sequence) => node = next,
+                    _ => break,
+                }
+            }
+
+            if let Some(before) = before.as_deref_mut() {
+                before[level] = node;
+            }
+        }
+
+        next
+    }
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    fn precedes(&self, key: &K, sequence: u64) -> bool {
+        self.key < *key || (self.key == *key && self.sequence > sequence)
+    }
+
+    /// Collects the versions of the key of the node, which follow each other from this one, and
+    /// returns them with the first node of the next key.
+    fn history(&self) -> (History<V>, *mut Node<K, V>) {
+        let mut history = History::new(self.sequence, self.value.clone());
+        let mut node = self.next[0].load(Ordering::Acquire);
+
+        // SAFETY: nodes are freed only when the list is dropped.
+        while let Some(older) = unsafe { node.as_ref() }
+            && older.key == self.key
+        {
+            history.append_older(History::new(older.sequence, older.value.clone()));
+            node = older.next[0].load(Ordering::Acquire);
+        }
+
+        (history, node)
+    }
+}
+
+impl<K, V, A> Memtable<K, V> for SkipList<K, V, A>
+where
+    K: Clone + Ord + Send + Sync,
+    V: Clone + Send + Sync,
+    A: NodeAllocator<K, V>,
+{
+    fn insert(&self, key: K, sequence: u64, value: Value<V>) {
+        let mut random = self.writer.lock().unwrap();
+
+        let mut before = [ptr::null_mut(); MAX_HEIGHT];
+        self.seek(&key, sequence, Some(&mut before));
+
+        let node = self.allocator.allocate(Node {
+            key,
+            sequence,
+            value,
+            next: Default::default(),
+        });
+
+        for (level, &previous) in before.iter().enumerate().take(random_height(&mut random)) {
+            let links = self.links(previous);
+
+            // SAFETY: readers can't reach the node yet, and it stays where it is.
+            let next = unsafe { &(*node).next[level] };
+            next.store(links[level].load(Ordering::Acquire), Ordering::Relaxed);
+
+            // Publishes the node together with everything written into it above.
+            links[level].store(node, Ordering::Release);
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<History<V>> {
+        let node = self.seek(key, u64::MAX, None);
+
+        // SAFETY: nodes are freed only when the list is dropped.
+        let first = unsafe { node.as_ref() }?;
+
+        (first.key == *key).then(|| first.history().0)
+    }
+
+    fn range(&self, bounds: (Bound<K>, Bound<K>)) -> Vec<(K, History<V>)> {
+        let mut node = match &bounds.0 {
+            Bound::Included(start) | Bound::Excluded(start) => self.seek(start, u64::MAX, None),
+            Bound::Unbounded => self.head[0].load(Ordering::Acquire),
+        };
+
+        let mut pairs = Vec::new();
+
+        // SAFETY: nodes are freed only when the list is dropped.
+        while let Some(first) = unsafe { node.as_ref() } {
+            if !(Bound::Unbounded, bounds.1.as_ref()).contains(&first.key) {
+                break;
+            }
+
+            let (history, next) = first.history();
+
+            if bounds.contains(&first.key) {
+                pairs.push((first.key.clone(), history));
+            }
+
+            node = next;
+        }
+
+        pairs
+    }
+}
+
+impl<K, V, A> Drop for SkipList<K, V, A>
+where
+    V: Clone,
+    A: NodeAllocator<K, V>,
+{
+    fn drop(&mut self) {
+        let first = *self.head[0].get_mut();
+
+        // SAFETY: the list is gone after this, and its nodes with it.
+        unsafe { self.allocator.release(first) };
+    }
+}
+
+/// Draws the height of a new node from the xorshift generator at `state`, so each level is
+/// reached by a quarter of the nodes of the level below.
+fn random_height(state: &mut u64) -> usize {
+    let mut height = 1;
+
+    while height < MAX_HEIGHT {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+
+        if !state.is_multiple_of(4) {
+            break;
+        }
+
+        height += 1;
+    }
+
+    height
+}
@@ -0,0 +1,162 @@
+use crate::lsm_tree::{
+    Value,
+    history::History,
+    memtable::{Memtable, MemtableKind},
+};
+use std::{
+    ops::Bound,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+const KINDS: [MemtableKind; 3] = [
+    MemtableKind::BTree,
+    MemtableKind::SkipList,
+    MemtableKind::Arena,
+];
+
+#[test]
+fn test_versions_are_returned_from_the_newest() {
+    for kind in KINDS {
+        let memtable = kind.create::<String, String>();
+
+        memtable.insert("b".to_string(), 1, Value::Data("one".to_string()));
+        memtable.insert("a".to_string(), 2, Value::Data("two".to_string()));
+        memtable.insert("b".to_string(), 3, Value::Tombstone);
+        memtable.insert("b".to_string(), 4, Value::Data("four".to_string()));
+
+        let history = memtable.get(&"b".to_string()).unwrap();
+        assert_eq!(
+            versions(&history),
+            vec![
+                (4, Some("four".to_string())),
+                (3, None),
+                (1, Some("one".to_string())),
+            ],
+            "{kind:?}"
+        );
+
+        assert_eq!(
+            versions(&memtable.get(&"a".to_string()).unwrap()),
+            vec![(2, Some("two".to_string()))],
+            "{kind:?}"
+        );
+        assert!(memtable.get(&"c".to_string()).is_none(), "{kind:?}");
+    }
+}
+
+#[test]
+fn test_range_respects_bounds() {
+    for kind in KINDS {
+        let memtable = kind.create::<u32, u32>();
+
+        for sequence in 1..=3 {
+            for key in (0..100).rev() {
+                memtable.insert(key, sequence, Value::Data(key));
+            }
+        }
+
+        let keys = |bounds| -> Vec<u32> {
+            memtable
+                .range(bounds)
+                .into_iter()
+                .map(|(key, history)| {
+                    assert_eq!(history.visible(u64::MAX).count(), 3);
+                    key
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            keys((Bound::Unbounded, Bound::Unbounded)),
+            (0..100).collect::<Vec<_>>(),
+            "{kind:?}"
+        );
+        assert_eq!(
+            keys((Bound::Included(10), Bound::Excluded(20))),
+            (10..20).collect::<Vec<_>>(),
+            "{kind:?}"
+        );
+        assert_eq!(
+            keys((Bound::Excluded(10), Bound::Included(20))),
+            (11..=20).collect::<Vec<_>>(),
+            "{kind:?}"
+        );
+        assert_eq!(
+            keys((Bound::Included(95), Bound::Unbounded)),
+            (95..100).collect::<Vec<_>>(),
+            "{kind:?}"
+        );
+        assert!(
+            keys((Bound::Excluded(99), Bound::Unbounded)).is_empty(),
+            "{kind:?}"
+        );
+    }
+}
+
+#[test]
+fn test_readers_see_whole_versions_while_a_writer_inserts() {
+    for kind in KINDS {
+        let memtable: Arc<dyn Memtable<u32, u32>> = Arc::from(kind.create::<u32, u32>());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let memtable = memtable.clone();
+                let done = done.clone();
+
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        let pairs = memtable.range((Bound::Unbounded, Bound::Unbounded));
+
+                        assert!(pairs.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+                        for (key, history) in pairs {
+                            let versions: Vec<_> = history.visible(u64::MAX).collect();
+
+                            assert!(versions.windows(2).all(|pair| pair[0].0 > pair[1].0));
+                            assert!(versions.iter().all(|(sequence, value)| {
+                                let expected = key + *sequence as u32;
+                                matches!(value, Value::Data(value) if *value == expected)
+                            }));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for sequence in 1..=5u64 {
+            for key in 0..2000 {
+                let key = (key * 7919) % 2000;
+                memtable.insert(key, sequence, Value::Data(key + sequence as u32));
+            }
+        }
+
+        done.store(true, Ordering::SeqCst);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let pairs = memtable.range((Bound::Unbounded, Bound::Unbounded));
+        assert_eq!(pairs.len(), 2000, "{kind:?}");
+        assert!(
+            pairs
+                .iter()
+                .all(|(_, history)| history.visible(u64::MAX).count() == 5),
+            "{kind:?}"
+        );
+    }
+}
+
+fn versions(history: &History<String>) -> Vec<(u64, Option<String>)> {
+    history
+        .visible(u64::MAX)
+        .map(|(sequence, value)| match value {
+            Value::Data(value) => (sequence, Some(value.clone())),
+            _ => (sequence, None),
+        })
+        .collect()
+}
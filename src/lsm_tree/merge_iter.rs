@@ -0,0 +1,82 @@
+use crate::lsm_tree::history::History;
+use std::{error::Error, iter::Peekable};
+
+pub(super) type Source<'a, K, V> =
+    Box<dyn Iterator<Item = Result<(K, History<V>), Box<dyn Error>>> + 'a>;
+
+/// Merges several sorted sources, ordered from the newest to the oldest, into one sorted stream.
+///
+/// When a key is present in several sources, the versions from all of them are combined into one
+/// history, from the newest to the oldest. It's up to the caller to decide which versions matter.
+pub(super) struct MergeIter<'a, K, V>
+where
+    V: Clone,
+{
+    sources: Vec<Peekable<Source<'a, K, V>>>,
+}
+
+impl<'a, K, V> MergeIter<'a, K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    pub(super) fn new(sources: Vec<Source<'a, K, V>>) -> Self {
+        Self {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+
+    /// Index of the newest source holding the smallest key among the heads of all sources.
+    fn smallest_source(&mut self) -> Option<Result<usize, Box<dyn Error>>> {
+        let mut smallest: Option<(usize, &K)> = None;
+
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Ok((key, _)))
+                    if smallest.is_none_or(|(_, smallest_key)| key < smallest_key) =>
+                {
+                    smallest = Some((i, key));
+                }
+                Some(Err(_)) => {
+                    let Some(Err(e)) = source.next() else {
+                        unreachable!()
+                    };
+                    return Some(Err(e));
+                }
+                _ => {}
+            }
+        }
+
+        smallest.map(|(i, _)| Ok(i))
+    }
+}
+
+impl<K, V> Iterator for MergeIter<'_, K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    type Item = Result<(K, History<V>), Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let source = match self.smallest_source()? {
+            Ok(source) => source,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let Some(Ok((key, mut history))) = self.sources[source].next() else {
+            unreachable!()
+        };
+
+        for older in self.sources.iter_mut().skip(source + 1) {
+            let head =
+                older.next_if(|head| matches!(head, Ok((older_key, _)) if older_key == &key));
+
+            if let Some(Ok((_, older_history))) = head {
+                history.append_older(older_history);
+            }
+        }
+
+        Some(Ok((key, history)))
+    }
+}
@@ -0,0 +1,11 @@
+/// Combines the operands written by [`LsmTree::merge`](crate::lsm_tree::LsmTree::merge) with the
+/// value of their key.
+///
+/// Operands are stored as they are and folded only when the key is read or compacted, so a merge
+/// costs as little as an insert. The same operands may be folded many times, so `merge` must
+/// always give the same result for the same arguments.
+pub trait MergeOperator<K, V>: Send + Sync {
+    /// Applies `operands`, from the oldest to the newest, to `existing`, the value `key` had
+    /// before them, if any.
+    fn merge(&self, key: &K, existing: Option<&V>, operands: &[V]) -> V;
+}
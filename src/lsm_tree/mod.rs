@@ -1,36 +1,574 @@
+mod clock;
+mod column_family;
+mod history;
+mod manifest;
+mod memtable;
+mod merge_iter;
+mod merge_operator;
+mod range_iter;
+mod snapshot;
 #[cfg(test)]
 mod tests;
+mod version;
+mod write_batch;
 
-use crate::sstable::SsTable;
+pub use clock::{Clock, SystemClock};
+pub use column_family::ColumnFamily;
+pub use memtable::MemtableKind;
+pub use merge_operator::MergeOperator;
+pub use range_iter::RangeIter;
+pub use snapshot::Snapshot;
+pub use write_batch::WriteBatch;
+
+use history::{History, Lookup};
+use manifest::{Manifest, VersionEdit};
+use merge_iter::{MergeIter, Source};
+use version::{TableFile, Version, WriteBuffer};
+use write_batch::Operation;
+
+use crate::{
+    lru_cache::LruCache,
+    sstable::{
+        BlockCache, BloomFilterPolicy, Compression, RangeTombstone, SsTable, SsTableOptions,
+    },
+    wal::Wal,
+};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
-    fs::File,
     hash::Hash,
-    io::{BufReader, BufWriter, Write},
+    ops::{Bound, Range, RangeBounds},
+    sync::{
+        Arc, Condvar, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
 };
 
+/// Persistent ordered map built as a log-structured merge-tree.
+///
+/// The tree may be shared between threads. Writes go to the WAL and to the active memtable. A
+/// full memtable becomes immutable and is flushed into a level0 SsTable by a background thread,
+/// while another background thread compacts the levels. Readers work on a snapshot of the
+/// memtables and SsTables taken when they start, so they never wait for a flush or a compaction.
+///
+/// Keys live in column families: separate keyspaces with their own memtables, SsTables and
+/// tuning, which share the WAL and the manifest of the tree. Methods of the tree itself work on
+/// the default family; others are reached through [`ColumnFamily`] handles.
 pub struct LsmTree<K, V>
 where
-    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
-    V: Clone + bincode::Encode + bincode::Decode<()>,
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    inner: Arc<Inner<K, V>>,
+    background: Vec<JoinHandle<()>>,
+}
+
+/// Part of the tree shared with the background threads.
+struct Inner<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
 {
-    map: BTreeMap<K, Value<V>>,
-    memtable_size: usize,
     data_directory: String,
-    ss_table_block_size: usize,
-    level_0_ss_tables: usize,
-    level_1_ss_tables: usize,
-    level_0_size: usize,
+    /// Current version of every column family, by family id. Writers and background threads
+    /// replace them, readers clone them.
+    versions: Mutex<Vec<Arc<Version<K, V>>>>,
+    /// Signalled whenever a new version is installed.
+    version_changed: Condvar,
+    /// Serializes writers, so records reach the WAL in the same order as the memtable.
+    writer: Mutex<Writer<K, V>>,
+    /// Column families and the layout of their levels as it's persisted in the manifest.
+    state: Mutex<State<K>>,
+    /// Log of the changes of `state`, always appended while holding `state`.
+    manifest: Mutex<Manifest<K>>,
+    /// Signalled whenever the layout changes, so the compaction thread can check for work.
+    state_changed: Condvar,
+    /// Held for the whole time of a compaction, so compactions never run concurrently.
+    compaction: Mutex<()>,
+    /// Recently used SsTables with their meta blocks loaded and data files open, by table id.
+    table_cache: Arc<TableCache<K, V>>,
+    /// Data blocks of all SsTables of the tree.
+    block_cache: Arc<BlockCache>,
+    /// Applies operands written by [`LsmTree::merge`]. Set after the tree is opened, so it's
+    /// missing until then.
+    merge_operator: RwLock<Option<Arc<dyn MergeOperator<K, V>>>>,
+    /// Tells when entries with a time to live expire.
+    clock: RwLock<Arc<dyn Clock>>,
+    /// Sequence number of the last write readers may see.
+    last_sequence: AtomicU64,
+    /// Number of live snapshots by their sequence numbers.
+    snapshots: Mutex<BTreeMap<u64, usize>>,
+    purged_tombstones: AtomicU64,
+    shutdown: AtomicBool,
+    /// First error a background thread has failed with. Once it's set, writes fail as well.
+    background_error: Mutex<Option<String>>,
+}
+
+/// Steps of flushes, compactions and opening a tree which leave files on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// The SsTable of a memtable is written, but the manifest doesn't know about it yet.
+    FlushTableWritten,
+    /// The manifest holds the new SsTable, but the WAL of the memtable is still there.
+    FlushEditLogged,
+    /// WALs holding only flushed writes are removed, but the memtable is still in memory.
+    FlushWalsRemoved,
+    /// An SsTable made by compaction is written, but the manifest doesn't know about it yet.
+    CompactionTableWritten,
+    /// The manifest holds the result of compaction, but the replaced SsTables are still there.
+    CompactionEditLogged,
+    /// A new manifest is written, but `CURRENT` still names the old one.
+    ManifestWritten,
+    /// `CURRENT` names the new manifest, but the old one is still there.
+    CurrentSwitched,
+}
+
+/// Step at which the tree in each data directory stops as if the process was killed. Only tests
+/// set it.
+#[cfg(test)]
+static CRASH_AT: Mutex<BTreeMap<String, Step>> = Mutex::new(BTreeMap::new());
+
+/// Fails like a killed process would when a test asked for a crash of the tree in
+/// `data_directory` at `step`.
+#[cfg(test)]
+fn reach(data_directory: &str, step: Step) -> Result<(), Box<dyn Error>> {
+    if CRASH_AT.lock().unwrap().get(data_directory) == Some(&step) {
+        return Err(format!("simulated crash at {step:?}").into());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(test))]
+fn reach(_data_directory: &str, _step: Step) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+struct Writer<K, V>
+where
+    V: Clone,
+{
+    /// WAL receiving new writes of all column families.
+    wal: Wal<WalRecord<K, V>>,
+    wal_id: u64,
+}
+
+/// Counters describing the work an [`LsmTree`] has done since it was opened.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LsmTreeStats {
+    /// Tombstones dropped by compaction because no older version of their key was left.
+    pub purged_tombstones: u64,
+    /// SsTable lookups served from the table cache.
+    pub table_cache_hits: u64,
+    /// SsTable lookups which had to load the table from disk.
+    pub table_cache_misses: u64,
+    /// Data block reads served from the block cache.
+    pub block_cache_hits: u64,
+    /// Data block reads which had to go to disk.
+    pub block_cache_misses: u64,
+}
+
+/// Tuning of an [`LsmTree`] or of one of its column families. The table cache and the block cache
+/// are shared by all families, so only the config the tree is created with sizes them.
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode)]
+pub struct LsmTreeConfig {
+    /// Number of entries after which the memtable is flushed into a level0 SsTable, unless it
+    /// reaches `memtable_max_bytes` first. Not limited by default. Every write of a key counts as
+    /// an entry, even when the key is in the memtable already, and so does every deleted range.
+    pub memtable_size: usize,
+    /// Approximate size in bytes of the entries after which the memtable is flushed. Unlike the
+    /// number of entries, it keeps the size of level0 SsTables steady when values vary in size.
+    pub memtable_max_bytes: usize,
+    /// Data structure holding the entries of memtables.
+    pub memtable: MemtableKind,
+    /// Whether writes are forced to the disk before they return, so they survive a crash of the
+    /// machine and not only of the process. Off by default, as it takes an fsync per write. A
+    /// write to several column families is synced if any of them asks for it.
+    pub sync_writes: bool,
+    /// Number of full memtables which may wait for a flush. Writes stall while all of them are
+    /// taken.
+    pub max_immutable_memtables: usize,
+    /// Number of level0 SsTables after which they are compacted into level1.
+    pub level_0_size: usize,
+    /// Number of entries after which a block of an SsTable is closed, unless it reaches
+    /// `ss_table_block_bytes` first. Not limited by default.
+    pub ss_table_block_size: usize,
+    /// Size in bytes of the entries after which a block of an SsTable is closed.
+    pub ss_table_block_bytes: usize,
+    /// Number of entries from one key stored whole to the next one within a block of an SsTable.
+    /// Keys in between are stored without the prefix they share with the key before them.
+    pub ss_table_restart_interval: usize,
+    /// Size in bytes after which a partition of the index of an SsTable is closed. Indexes are
+    /// not partitioned by default, which suits all but very big SsTables.
+    pub ss_table_index_partition_bytes: Option<usize>,
+    /// Whether partitions of SsTable indexes stay in memory once they're read, for as long as
+    /// their tables are open, instead of competing for the block cache with data blocks. Pinned
+    /// partitions still count against the capacity of the block cache, which caches fewer data
+    /// blocks to make room for them.
+    pub pin_index_partitions: bool,
+    /// Codec used to compress blocks of new SsTables.
+    pub ss_table_compression: Compression,
+    /// Sizing of bloom filters of new SsTables.
+    pub ss_table_bloom_filter: BloomFilterPolicy,
+    /// Number of levels, including level0. The last level is not limited in size.
+    pub levels: usize,
+    /// Maximum size of level1 in bytes.
+    pub level_1_max_size: u64,
+    /// How many times each next level may be bigger than the previous one.
+    pub level_size_ratio: u64,
+    /// Size in bytes after which compaction starts a new SsTable.
+    pub ss_table_target_size: u64,
+    /// Number of SsTables kept loaded with their data files open. The least recently used table
+    /// is closed to make room for another one.
+    pub max_open_files: usize,
+    /// Total size in bytes of the data blocks kept in memory by the block cache, and of the index
+    /// partitions pinned by column families with `pin_index_partitions`.
+    pub block_cache_size: usize,
+    /// Whether blocks read by compaction are put into the block cache. Compaction reads every
+    /// block of its tables just once, so by default it leaves the cache to the blocks of lookups.
+    pub compaction_fills_block_cache: bool,
+}
+
+impl Default for LsmTreeConfig {
+    fn default() -> Self {
+        Self {
+            memtable_size: usize::MAX,
+            memtable_max_bytes: 4 * 1024 * 1024,
+            memtable: MemtableKind::default(),
+            sync_writes: false,
+            max_immutable_memtables: 2,
+            level_0_size: 4,
+            ss_table_block_size: usize::MAX,
+            ss_table_block_bytes: 4 * 1024,
+            ss_table_restart_interval: 16,
+            ss_table_index_partition_bytes: None,
+            pin_index_partitions: false,
+            ss_table_compression: Compression::None,
+            ss_table_bloom_filter: BloomFilterPolicy::default(),
+            levels: 7,
+            level_1_max_size: 10 * 1024 * 1024,
+            level_size_ratio: 10,
+            ss_table_target_size: 2 * 1024 * 1024,
+            max_open_files: 1000,
+            block_cache_size: 8 * 1024 * 1024,
+            compaction_fills_block_cache: false,
+        }
+    }
+}
+
+impl LsmTreeConfig {
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.levels < 2 {
+            return Err("LSM tree needs at least two levels".into());
+        }
+
+        if self.max_immutable_memtables == 0 {
+            return Err("LSM tree needs room for at least one immutable memtable".into());
+        }
+
+        // Level0 would always need compaction, so the compaction thread would never rest.
+        if self.level_0_size == 0 {
+            return Err("LSM tree needs room for at least one level0 SsTable".into());
+        }
+
+        if self.level_size_ratio == 0 {
+            return Err("LSM tree needs a level size ratio of at least one".into());
+        }
+
+        if self.ss_table_target_size == 0 {
+            return Err("LSM tree needs a target size of SsTables of at least one byte".into());
+        }
+
+        self.ss_table_options().check()
+    }
+
+    fn ss_table_options(&self) -> SsTableOptions {
+        SsTableOptions {
+            block_size: self.ss_table_block_size,
+            block_bytes: self.ss_table_block_bytes,
+            restart_interval: self.ss_table_restart_interval,
+            index_partition_bytes: self.ss_table_index_partition_bytes,
+            compression: self.ss_table_compression,
+            bloom_filter: self.ss_table_bloom_filter,
+        }
+    }
+}
+
+type Pair<K, V> = Result<(K, V), Box<dyn Error>>;
+
+/// Id of the column family the tree is created with.
+const DEFAULT_FAMILY: usize = 0;
+
+const DEFAULT_FAMILY_NAME: &str = "default";
+
+/// Record of a WAL: all changes of one write with its sequence number.
+#[derive(bincode::Encode, bincode::Decode)]
+struct WalRecord<K, V>
+where
+    V: Clone,
+{
+    sequence: u64,
+    changes: Vec<Changes<K, V>>,
 }
 
+/// Changes a write makes in one column family.
 #[derive(bincode::Encode, bincode::Decode)]
-struct State {
-    ss_table_block_size: usize,
-    memtable_size: usize,
-    level_0_ss_tables: usize,
-    level_1_ss_tables: usize,
-    level_0_size: usize,
+struct Changes<K, V>
+where
+    V: Clone,
+{
+    family: usize,
+    pairs: Vec<(K, Value<V>)>,
+    /// Starts and ends of the deleted ranges.
+    deleted_ranges: Vec<(K, K)>,
+}
+
+/// SsTable of the tree, shared between the table cache and its readers.
+type Table<K, V> = Arc<SsTable<K, History<V>>>;
+
+type TableCache<K, V> = Mutex<LruCache<u64, Table<K, V>>>;
+
+/// Pairs and deleted ranges a write batch makes in one column family.
+type BatchChanges<K, V> = (BTreeMap<K, Value<V>>, Vec<(K, K)>);
+
+#[derive(Clone)]
+struct State<K> {
+    /// Column families by their ids.
+    families: Vec<FamilyState<K>>,
+    /// Number of the next file of the tree. SsTables, WALs and manifests share the numbers, and a
+    /// number is never used twice.
+    next_file: u64,
+    /// Highest sequence number of the writes in SsTables.
+    last_sequence: u64,
+}
+
+/// Layout of the levels of a column family.
+#[derive(Clone)]
+struct FamilyState<K> {
+    name: String,
+    config: LsmTreeConfig,
+    /// SsTables of every level. Level0 tables are ordered from the oldest to the newest and may
+    /// overlap, tables of the other levels are ordered by keys and never overlap.
+    levels: Vec<Vec<TableMeta<K>>>,
+    /// Last key of the table most recently compacted from each level, so the next compaction of
+    /// the level picks the table right after it.
+    compaction_pointers: Vec<Option<K>>,
+    /// WAL files with smaller ids hold no writes of the family which are not flushed yet.
+    oldest_wal: u64,
+}
+
+impl<K> State<K>
+where
+    K: Clone + Ord,
+{
+    fn new() -> Self {
+        Self {
+            families: Vec::new(),
+            next_file: 0,
+            last_sequence: 0,
+        }
+    }
+
+    fn new_file_number(&mut self) -> u64 {
+        self.next_file += 1;
+        self.next_file - 1
+    }
+
+    fn family_id(&self, name: &str) -> Option<usize> {
+        self.families.iter().position(|family| family.name == name)
+    }
+
+    /// WAL files with smaller ids hold no writes which are not flushed yet.
+    fn oldest_wal(&self) -> u64 {
+        self.families
+            .iter()
+            .map(|family| family.oldest_wal)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn apply(&mut self, edit: VersionEdit<K>) {
+        if let Some((name, config)) = edit.created_family {
+            self.families.push(FamilyState::new(name, config));
+        }
+
+        let family = &mut self.families[edit.family];
+
+        for (level, id) in edit.removed_tables {
+            family.levels[level].retain(|table| table.id != id);
+        }
+
+        for (level, table) in edit.added_tables {
+            family.levels[level].push(table);
+
+            if level > 0 {
+                family.levels[level].sort_by(|a, b| a.first_key.cmp(&b.first_key));
+            }
+        }
+
+        for (level, pointer) in edit.compaction_pointers {
+            family.compaction_pointers[level] = Some(pointer);
+        }
+
+        if let Some(oldest_wal) = edit.oldest_wal {
+            family.oldest_wal = oldest_wal;
+        }
+
+        for (family, oldest_wal) in edit.idle_oldest_wals {
+            let family = &mut self.families[family];
+            family.oldest_wal = family.oldest_wal.max(oldest_wal);
+        }
+
+        if let Some(next_file) = edit.next_file {
+            self.next_file = self.next_file.max(next_file);
+        }
+
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = last_sequence;
+        }
+    }
+
+    /// Describes the whole layout with an edit per column family, which start a new manifest.
+    fn to_edits(&self) -> Vec<VersionEdit<K>> {
+        self.families
+            .iter()
+            .enumerate()
+            .map(|(id, family)| {
+                let added_tables = family
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(level, tables)| {
+                        tables.iter().map(move |table| (level, table.clone()))
+                    })
+                    .collect();
+
+                let compaction_pointers = family
+                    .compaction_pointers
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(level, pointer)| Some((level, pointer.clone()?)))
+                    .collect();
+
+                VersionEdit {
+                    family: id,
+                    created_family: Some((family.name.clone(), family.config.clone())),
+                    added_tables,
+                    compaction_pointers,
+                    next_file: Some(self.next_file),
+                    oldest_wal: Some(family.oldest_wal),
+                    last_sequence: Some(self.last_sequence),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+}
+
+impl<K> FamilyState<K>
+where
+    K: Clone + Ord,
+{
+    fn new(name: String, config: LsmTreeConfig) -> Self {
+        Self {
+            name,
+            levels: vec![Vec::new(); config.levels],
+            compaction_pointers: vec![None; config.levels],
+            oldest_wal: 0,
+            config,
+        }
+    }
+
+    fn needs_compaction(&self) -> bool {
+        self.levels[0].len() >= self.config.level_0_size
+            || (1..self.levels.len() - 1)
+                .any(|level| self.level_size(level) > self.max_level_size(level))
+    }
+
+    /// Picks tables of a level in a round-robin manner, so every part of the key space gets
+    /// compacted eventually.
+    fn pick_table_to_compact(&mut self, level: usize) -> TableMeta<K> {
+        let tables = &self.levels[level];
+
+        let table = self.compaction_pointers[level]
+            .as_ref()
+            .and_then(|pointer| tables.iter().find(|table| &table.first_key > pointer))
+            .unwrap_or(&tables[0])
+            .clone();
+
+        self.compaction_pointers[level] = Some(table.last_key.clone());
+
+        table
+    }
+
+    /// Checks if any table of the levels below `level` covers `key`.
+    fn may_contain_below(&self, level: usize, key: &K) -> bool {
+        self.levels.iter().skip(level + 1).any(|tables| {
+            let candidate = tables.partition_point(|table| &table.last_key < key);
+            tables
+                .get(candidate)
+                .is_some_and(|table| &table.first_key <= key)
+        })
+    }
+
+    /// Checks if any table of the levels below `level` overlaps keys from `start` up to `end`.
+    fn may_overlap_below(&self, level: usize, start: &K, end: &K) -> bool {
+        let range = start.clone()..end.clone();
+
+        self.levels
+            .iter()
+            .skip(level + 1)
+            .flatten()
+            .any(|table| table.overlaps(&range))
+    }
+
+    fn level_size(&self, level: usize) -> u64 {
+        self.levels[level].iter().map(|table| table.size).sum()
+    }
+
+    /// Saturates at `u64::MAX`, as deep levels of steep trees would overflow it.
+    fn max_level_size(&self, level: usize) -> u64 {
+        u32::try_from(level - 1)
+            .ok()
+            .and_then(|exponent| self.config.level_size_ratio.checked_pow(exponent))
+            .map_or(u64::MAX, |ratio| {
+                self.config.level_1_max_size.saturating_mul(ratio)
+            })
+    }
+}
+
+#[derive(bincode::Encode, bincode::Decode, Clone, Debug)]
+struct TableMeta<K> {
+    id: u64,
+    first_key: K,
+    last_key: K,
+    size: u64,
+    /// Sequence numbers of the oldest and the newest version the table holds.
+    smallest_sequence: u64,
+    largest_sequence: u64,
+}
+
+impl<K> TableMeta<K>
+where
+    K: Ord,
+{
+    fn overlaps(&self, range: &impl RangeBounds<K>) -> bool {
+        let after_start = match range.start_bound() {
+            Bound::Included(start) => &self.last_key >= start,
+            Bound::Excluded(start) => &self.last_key > start,
+            Bound::Unbounded => true,
+        };
+
+        let before_end = match range.end_bound() {
+            Bound::Included(end) => &self.first_key <= end,
+            Bound::Excluded(end) => &self.first_key < end,
+            Bound::Unbounded => true,
+        };
+
+        after_start && before_end
+    }
 }
 
 #[derive(bincode::Encode, bincode::Decode, Clone)]
@@ -40,22 +578,40 @@ where
 {
     Data(T),
     Tombstone,
+    /// Operand of [`LsmTree::merge`], applied to the older versions of the key when it's read.
+    Merge(T),
+    /// Value which reads as a tombstone from `expires_at`, in milliseconds since the Unix epoch.
+    Expiring {
+        data: T,
+        expires_at: u64,
+    },
+}
+
+impl<T> Value<T>
+where
+    T: Clone,
+{
+    fn expired(&self, now: u64) -> bool {
+        matches!(self, Value::Expiring { expires_at, .. } if *expires_at <= now)
+    }
 }
 
 impl<K, V> Drop for LsmTree<K, V>
 where
-    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
-    V: Clone + bincode::Encode + bincode::Decode<()>,
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
 {
     fn drop(&mut self) {
-        self.flush().unwrap();
+        let flushed = self.flush();
+        self.stop_background_work();
+        flushed.unwrap();
     }
 }
 
 impl<K, V> LsmTree<K, V>
 where
-    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
-    V: Clone + bincode::Encode + bincode::Decode<()>,
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
 {
     pub fn new(
         data_directory: String,
@@ -63,157 +619,1441 @@ where
         level_0_size: usize,
         ss_table_block_size: usize,
     ) -> Result<Self, Box<dyn Error>> {
-        std::fs::create_dir_all(format!("{data_directory}/level0"))?;
-        std::fs::create_dir_all(format!("{data_directory}/level1"))?;
-
-        Ok(Self {
-            map: BTreeMap::new(),
+        let config = LsmTreeConfig {
             memtable_size,
-            data_directory,
-            ss_table_block_size,
-            level_0_ss_tables: 0,
-            level_1_ss_tables: 0,
             level_0_size,
-        })
+            ss_table_block_size,
+            ..Default::default()
+        };
+
+        Self::with_config(data_directory, config)
     }
 
-    pub fn load(data_directory: String) -> Result<Self, Box<dyn Error>> {
-        let reader = BufReader::new(File::open(format!("{data_directory}/state"))?);
+    /// Creates a tree whose default column family is tuned by `config`.
+    pub fn with_config(
+        data_directory: String,
+        config: LsmTreeConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        config.check()?;
 
-        let State {
-            ss_table_block_size,
-            memtable_size,
-            level_0_ss_tables,
-            level_1_ss_tables,
-            level_0_size,
-        }: State = bincode::decode_from_reader(reader, bincode::config::standard())?;
+        for level in 0..config.levels {
+            std::fs::create_dir_all(format!("{data_directory}/level{level}"))?;
+        }
 
-        Ok(Self {
-            map: BTreeMap::new(),
-            memtable_size,
+        std::fs::create_dir_all(format!("{data_directory}/wal"))?;
+
+        let mut state = State::new();
+        state
+            .families
+            .push(FamilyState::new(DEFAULT_FAMILY_NAME.to_string(), config));
+
+        let wal_id = state.new_file_number();
+        let wal = Wal::create(&wal_path(&data_directory, wal_id))?;
+        let kind = state.families[DEFAULT_FAMILY].config.memtable;
+        let memtables = vec![vec![WriteBuffer::new(wal_id, kind)]];
+
+        let manifest_number = state.new_file_number();
+        let manifest = Manifest::create(&data_directory, manifest_number, &state)?;
+
+        let inner = Inner::new(
             data_directory,
-            ss_table_block_size,
-            level_0_ss_tables,
-            level_1_ss_tables,
-            level_0_size,
-        })
+            state,
+            manifest,
+            Writer { wal, wal_id },
+            memtables,
+            0,
+        );
+
+        Ok(Self::start(inner))
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Result<(), Box<dyn Error>> {
-        if self.map.len() == self.memtable_size {
-            self.flush()?;
+    /// Opens the tree stored in `data_directory`, replaying its manifest and WALs.
+    ///
+    /// Trees written before the manifest, whose layout is a single `state` file, can't be loaded,
+    /// as their SsTables store values in an encoding the tree no longer reads. Loading one fails
+    /// with an "unsupported pre-manifest layout" error; its pairs have to be read out with the
+    /// version which wrote it and inserted into a new tree.
+    pub fn load(data_directory: String) -> Result<Self, Box<dyn Error>> {
+        let mut state: State<K> = Manifest::recover(&data_directory)?;
+
+        // Column families share the level directories, as file numbers never repeat.
+        let levels = state
+            .families
+            .iter()
+            .map(|family| family.config.levels)
+            .max()
+            .unwrap_or(0);
+
+        // Files created after the last edit of the manifest are not recorded in it, but their
+        // numbers are taken all the same.
+        let directories = (0..levels)
+            .map(|level| format!("{data_directory}/level{level}"))
+            .chain([data_directory.clone(), format!("{data_directory}/wal")]);
+
+        for directory in directories {
+            for entry in std::fs::read_dir(directory)? {
+                let name = entry?.file_name();
+
+                if let Some(number) = manifest::file_number(&name.to_string_lossy()) {
+                    state.next_file = state.next_file.max(number + 1);
+                }
+            }
         }
 
-        self.map.insert(key, Value::Data(value));
-        Ok(())
-    }
+        // A flush or a compaction interrupted by a crash leaves behind tables the manifest doesn't
+        // know about, or tables it doesn't need anymore.
+        for level in 0..levels {
+            let directory = format!("{data_directory}/level{level}");
 
-    pub fn get(&self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
-        if let Some(value) = self.map.get(key) {
-            return match value {
-                Value::Data(d) => Ok(Some(d.clone())),
-                Value::Tombstone => Ok(None),
-            };
-        };
+            let tables: Vec<_> = state
+                .families
+                .iter()
+                .filter_map(|family| family.levels.get(level))
+                .flatten()
+                .collect();
 
-        for ss_table in (0..self.level_0_ss_tables).rev() {
-            let path = format!("{}/level0/{ss_table}", self.data_directory);
-            let ss_table = SsTable::<K, Value<V>>::load(path)?;
+            for entry in std::fs::read_dir(&directory)? {
+                let name = entry?.file_name().to_string_lossy().into_owned();
 
-            if let Some(value) = ss_table.get(key)? {
-                return match value {
-                    Value::Data(d) => Ok(Some(d)),
-                    Value::Tombstone => Ok(None),
-                };
+                let live = !name.ends_with(".tmp")
+                    && manifest::file_number(&name)
+                        .is_some_and(|number| tables.iter().any(|table| table.id == number));
+
+                if !live {
+                    std::fs::remove_file(format!("{directory}/{name}"))?;
+                }
             }
         }
 
-        for ss_table in (0..self.level_1_ss_tables).rev() {
-            let path = format!("{}/level1/{ss_table}", self.data_directory);
-            let ss_table = SsTable::<K, Value<V>>::load(path)?;
+        let mut wal_ids = Vec::new();
 
-            if let Some(value) = ss_table.get(key)? {
-                return match value {
-                    Value::Data(d) => Ok(Some(d)),
-                    Value::Tombstone => Ok(None),
-                };
+        for id in list_wals(&data_directory)? {
+            if id < state.oldest_wal() {
+                std::fs::remove_file(wal_path(&data_directory, id))?;
+            } else {
+                wal_ids.push(id);
             }
         }
 
-        Ok(None)
-    }
+        wal_ids.sort();
+
+        // Writes which have not reached a level0 SsTable before the tree was closed (or the
+        // process was killed) are still in the WALs. Every WAL gives each column family a
+        // memtable with the writes of the family it holds.
+        let mut memtables: Vec<Vec<WriteBuffer<K, V>>> =
+            state.families.iter().map(|_| Vec::new()).collect();
+        let mut active = None;
+        let mut last_sequence = state.last_sequence;
+
+        for id in wal_ids {
+            let (wal, records) = Wal::<WalRecord<K, V>>::open(&wal_path(&data_directory, id))?;
+            let replayed: Vec<_> = state
+                .families
+                .iter()
+                .map(|family| WriteBuffer::new(id, family.config.memtable))
+                .collect();
+
+            for record in records {
+                last_sequence = last_sequence.max(record.sequence);
+
+                for changes in record.changes {
+                    let Some(family) = state.families.get(changes.family) else {
+                        return Err("WAL refers to an unknown column family".into());
+                    };
+
+                    // Writes of the family in older WALs are already flushed.
+                    if id >= family.oldest_wal {
+                        replayed[changes.family].insert(
+                            record.sequence,
+                            changes.pairs,
+                            changes.deleted_ranges,
+                        );
+                    }
+                }
+            }
+
+            for (memtables, memtable) in memtables.iter_mut().zip(replayed) {
+                if !memtable.is_empty() {
+                    memtables.push(memtable);
+                }
+            }
+
+            active = Some((wal, id));
+        }
+
+        let (wal, wal_id) = match active {
+            Some(active) => active,
+            None => {
+                let wal_id = state.new_file_number();
+                let wal = Wal::create(&wal_path(&data_directory, wal_id))?;
+
+                if state
+                    .families
+                    .iter()
+                    .any(|family| family.config.sync_writes)
+                {
+                    sync_wal_directory(&data_directory)?;
+                }
 
-    pub fn delete(&mut self, key: K) -> Result<Option<V>, Box<dyn Error>> {
-        let value = self.get(&key)?;
-        if value.is_none() {
-            return Ok(None);
+                (wal, wal_id)
+            }
         };
 
-        if self.map.len() == self.memtable_size {
-            self.flush()?;
+        // The newest memtable of every family keeps receiving writes, the older ones get flushed
+        // by the background thread.
+        for (memtables, family) in memtables.iter_mut().zip(&state.families) {
+            if memtables.is_empty() {
+                memtables.push(WriteBuffer::new(wal_id, family.config.memtable));
+            }
         }
 
-        self.map.insert(key, Value::Tombstone);
-        Ok(value)
+        // Every run starts a new manifest, so the edits of the previous runs don't pile up.
+        let manifest_number = state.new_file_number();
+        let manifest = Manifest::create(&data_directory, manifest_number, &state)?;
+
+        let inner = Inner::new(
+            data_directory,
+            state,
+            manifest,
+            Writer { wal, wal_id },
+            memtables,
+            last_sequence,
+        );
+
+        Ok(Self::start(inner))
     }
 
-    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut map = BTreeMap::new();
-        std::mem::swap(&mut self.map, &mut map);
+    fn start(inner: Inner<K, V>) -> Self {
+        let inner = Arc::new(inner);
 
-        let path = format!("{}/level0/{}", self.data_directory, self.level_0_ss_tables);
-        let _ = SsTable::new(map, &path, self.ss_table_block_size)?;
+        let flusher = {
+            let inner = inner.clone();
+            std::thread::spawn(move || inner.run_flushes())
+        };
 
-        self.level_0_ss_tables += 1;
+        let compactor = {
+            let inner = inner.clone();
+            std::thread::spawn(move || inner.run_compactions())
+        };
 
-        if self.level_0_ss_tables == self.level_0_size {
-            self.compact()?;
+        Self {
+            inner,
+            background: vec![flusher, compactor],
         }
+    }
 
-        let mut writer = BufWriter::new(File::create(format!("{}/state", self.data_directory))?);
+    /// Creates a column family named `name` tuned by `config`. The family is recorded in the
+    /// manifest right away, so it's there when the tree is loaded.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        config: LsmTreeConfig,
+    ) -> Result<ColumnFamily<'_, K, V>, Box<dyn Error>> {
+        let id = self.inner.create_family(name, config)?;
+        Ok(ColumnFamily::new(self, id))
+    }
 
-        let state = State {
-            ss_table_block_size: self.ss_table_block_size,
-            memtable_size: self.memtable_size,
-            level_0_ss_tables: self.level_0_ss_tables,
-            level_1_ss_tables: self.level_1_ss_tables,
-            level_0_size: self.level_0_size,
-        };
+    pub fn column_family(&self, name: &str) -> Result<ColumnFamily<'_, K, V>, Box<dyn Error>> {
+        let state = self.inner.state.lock().unwrap();
 
-        let encoded_state = bincode::encode_to_vec(state, bincode::config::standard())?;
+        match state.family_id(name) {
+            Some(id) => Ok(ColumnFamily::new(self, id)),
+            None => Err(format!("LSM tree has no column family named {name}").into()),
+        }
+    }
 
-        writer.write_all(encoded_state.as_slice())?;
+    /// Names of all column families, starting with the default one.
+    pub fn column_families(&self) -> Vec<String> {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .families
+            .iter()
+            .map(|family| family.name.clone())
+            .collect()
+    }
 
-        Ok(())
+    fn default_family(&self) -> ColumnFamily<'_, K, V> {
+        ColumnFamily::new(self, DEFAULT_FAMILY)
     }
 
-    pub fn compact(&mut self) -> Result<(), Box<dyn Error>> {
-        let iters = (0..self.level_0_ss_tables)
-            .map(|ss_table| {
-                let path = format!("{}/level0/{ss_table}", self.data_directory);
-                SsTable::<K, Value<V>>::load(path)?.iter()
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+    pub fn insert(&self, key: K, value: V) -> Result<(), Box<dyn Error>> {
+        self.default_family().insert(key, value)
+    }
 
-        let map = iters
+    /// Inserts a pair which reads as deleted once `ttl` has passed. Compaction drops it for good
+    /// after that.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Result<(), Box<dyn Error>> {
+        self.default_family().insert_with_ttl(key, value, ttl)
+    }
+
+    /// Applies all changes of `batch` at once, in whichever column families they are: they are
+    /// written as a single WAL record into a single memtable of each family, and snapshots see
+    /// either none or all of them.
+    pub fn write(&self, batch: WriteBatch<K, V>) -> Result<(), Box<dyn Error>> {
+        let mut writer = self.inner.writer.lock().unwrap();
+        let mut families: BTreeMap<usize, BatchChanges<K, V>> = BTreeMap::new();
+
+        for (family, operation) in batch.operations {
+            let (pairs, deleted_ranges) = families.entry(family).or_default();
+
+            match operation {
+                Operation::Put(key, value) => {
+                    pairs.insert(key, Value::Data(value));
+                }
+                Operation::Delete(key) => {
+                    pairs.insert(key, Value::Tombstone);
+                }
+                Operation::DeleteRange(range) => {
+                    // The range tombstone hides only older writes, so the pairs it deletes from
+                    // the batch itself are dropped here.
+                    pairs.retain(|key, _| !range.contains(key));
+
+                    if !range.is_empty() {
+                        deleted_ranges.push((range.start, range.end));
+                    }
+                }
+            }
+        }
+
+        let changes: Vec<_> = families
             .into_iter()
-            .flatten()
-            .collect::<Result<BTreeMap<K, Value<V>>, _>>()?;
+            .filter(|(_, (pairs, deleted_ranges))| !pairs.is_empty() || !deleted_ranges.is_empty())
+            .map(|(family, (pairs, deleted_ranges))| Changes {
+                family,
+                pairs: pairs.into_iter().collect(),
+                deleted_ranges,
+            })
+            .collect();
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.write(&mut writer, changes)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
+        self.default_family().get(key)
+    }
+
+    /// Returns an ordered iterator over all live pairs whose keys are in `range`, merging the
+    /// memtables with all SsTables which may hold keys of the range.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<RangeIter<K, V>, Box<dyn Error>> {
+        self.default_family().range(range)
+    }
+
+    /// Returns a view of all column families of the tree which sees only the writes made so far.
+    pub fn snapshot(&self) -> Snapshot<'_, K, V> {
+        let mut snapshots = self.inner.snapshots.lock().unwrap();
+
+        let sequence = self.inner.last_sequence.load(Ordering::SeqCst);
+        *snapshots.entry(sequence).or_default() += 1;
+
+        Snapshot::new(self, sequence)
+    }
+
+    /// Returns an ordered iterator over all live pairs whose keys start with `prefix`.
+    pub fn prefix(&self, prefix: K) -> Result<impl Iterator<Item = Pair<K, V>>, Box<dyn Error>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.default_family().prefix(prefix)
+    }
+
+    pub fn delete(&self, key: K) -> Result<Option<V>, Box<dyn Error>> {
+        self.default_family().delete(key)
+    }
+
+    /// Deletes `key` without looking it up first, so it never reads SsTables. A tombstone is
+    /// written even when the key doesn't exist.
+    pub fn remove(&self, key: K) -> Result<(), Box<dyn Error>> {
+        self.default_family().remove(key)
+    }
+
+    /// Deletes `key` and returns whether it existed. Unlike [`delete`](Self::delete), the old
+    /// value is not returned, and when the bloom filters of all SsTables rule the key out, the
+    /// answer comes without reading any data block and no tombstone is written.
+    pub fn remove_checked(&self, key: K) -> Result<bool, Box<dyn Error>> {
+        self.default_family().remove_checked(key)
+    }
+
+    /// Sets the operator which applies the operands written by [`merge`](Self::merge). Trees
+    /// holding operands need it set again every time they are loaded.
+    pub fn with_merge_operator(self, operator: impl MergeOperator<K, V> + 'static) -> Self {
+        *self.inner.merge_operator.write().unwrap() = Some(Arc::new(operator));
+        self
+    }
+
+    /// Replaces the clock entries with a time to live are checked against, which is the clock of
+    /// the operating system by default.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        *self.inner.clock.write().unwrap() = Arc::new(clock);
+        self
+    }
+
+    /// Writes `operand` to be applied to the value of `key` by the merge operator of the tree,
+    /// without reading the value. Operands are applied lazily, when the key is read or compacted.
+    pub fn merge(&self, key: K, operand: V) -> Result<(), Box<dyn Error>> {
+        self.default_family().merge(key, operand)
+    }
+
+    /// Deletes all keys in `range` with a single range tombstone, no matter how many keys it
+    /// covers.
+    pub fn delete_range(&self, range: Range<K>) -> Result<(), Box<dyn Error>> {
+        self.default_family().delete_range(range)
+    }
 
-        let path = format!("{}/level1/{}", self.data_directory, self.level_1_ss_tables);
-        let _ = SsTable::new(map, &path, self.ss_table_block_size)?;
+    /// Makes the active memtables of all column families immutable and waits until all memtables
+    /// are written into level0 SsTables.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        self.inner.flush(&self.inner.family_ids())
+    }
 
-        let level_0 = format!("{}/level0", self.data_directory);
+    pub fn stats(&self) -> LsmTreeStats {
+        let table_cache = self.inner.table_cache.lock().unwrap();
 
-        for file in std::fs::read_dir(level_0)? {
-            let file = file?;
-            let path = file.path();
-            std::fs::remove_file(path)?;
+        LsmTreeStats {
+            purged_tombstones: self.inner.purged_tombstones.load(Ordering::SeqCst),
+            table_cache_hits: table_cache.hits(),
+            table_cache_misses: table_cache.misses(),
+            block_cache_hits: self.inner.block_cache.hits(),
+            block_cache_misses: self.inner.block_cache.misses(),
         }
+    }
 
-        self.level_1_ss_tables += 1;
-        self.level_0_ss_tables = 0;
+    /// Compacts all level0 SsTables into level1, and then pushes data further down from every
+    /// level which exceeds its size limit, one SsTable at a time, in every column family. Waits
+    /// for a compaction running in the background to finish first.
+    pub fn compact(&self) -> Result<(), Box<dyn Error>> {
+        for family in self.inner.family_ids() {
+            self.inner.compact(family)?;
+        }
 
         Ok(())
     }
+
+    /// Stops the background threads once the memtables waiting for a flush are written and all
+    /// due compactions are done. The active memtables stay in the WAL.
+    fn stop_background_work(&mut self) {
+        self.inner.shutdown.store(true, Ordering::SeqCst);
+
+        // Taking the locks makes sure no thread is between checking the flag and going to sleep.
+        drop(self.inner.versions.lock().unwrap());
+        self.inner.version_changed.notify_all();
+        drop(self.inner.state.lock().unwrap());
+        self.inner.state_changed.notify_all();
+
+        for thread in self.background.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    /// Sets up the tree with the memtables of every column family, by family id. Memtables of a
+    /// family go from the oldest one, and the last one receives new writes.
+    fn new(
+        data_directory: String,
+        state: State<K>,
+        manifest: Manifest<K>,
+        writer: Writer<K, V>,
+        memtables: Vec<Vec<WriteBuffer<K, V>>>,
+        last_sequence: u64,
+    ) -> Self {
+        let config = &state.families[DEFAULT_FAMILY].config;
+        let table_cache = Arc::new(Mutex::new(LruCache::new(config.max_open_files)));
+        let block_cache = Arc::new(BlockCache::new(config.block_cache_size));
+
+        let versions = state
+            .families
+            .iter()
+            .zip(memtables)
+            .map(|(family, mut memtables)| {
+                let memtable = memtables.pop().expect("column family has no memtable");
+                let immutable = memtables.into_iter().map(Arc::new).collect();
+
+                let levels = family
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, tables)| {
+                        tables
+                            .iter()
+                            .map(|table| {
+                                let path = ss_table_path(&data_directory, level, table.id);
+                                let table =
+                                    TableFile::new(level, table.clone(), path, table_cache.clone());
+                                Arc::new(table)
+                            })
+                            .collect()
+                    })
+                    .collect();
+
+                Arc::new(Version {
+                    config: Arc::new(family.config.clone()),
+                    memtable: Arc::new(memtable),
+                    immutable,
+                    levels,
+                })
+            })
+            .collect();
+
+        Self {
+            data_directory,
+            versions: Mutex::new(versions),
+            version_changed: Condvar::new(),
+            writer: Mutex::new(writer),
+            state: Mutex::new(state),
+            manifest: Mutex::new(manifest),
+            state_changed: Condvar::new(),
+            compaction: Mutex::new(()),
+            table_cache,
+            block_cache,
+            merge_operator: RwLock::new(None),
+            clock: RwLock::new(Arc::new(SystemClock)),
+            last_sequence: AtomicU64::new(last_sequence),
+            snapshots: Mutex::new(BTreeMap::new()),
+            purged_tombstones: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+            background_error: Mutex::new(None),
+        }
+    }
+
+    fn current(&self, family: usize) -> Arc<Version<K, V>> {
+        self.versions.lock().unwrap()[family].clone()
+    }
+
+    fn family_ids(&self) -> Vec<usize> {
+        (0..self.versions.lock().unwrap().len()).collect()
+    }
+
+    fn create_family(&self, name: &str, config: LsmTreeConfig) -> Result<usize, Box<dyn Error>> {
+        config.check()?;
+
+        // Taken first, so no write reaches the WAL the new family starts with before it exists.
+        let writer = self.writer.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        if state.family_id(name).is_some() {
+            return Err(format!("LSM tree already has a column family named {name}").into());
+        }
+
+        for level in 0..config.levels {
+            std::fs::create_dir_all(format!("{}/level{level}", self.data_directory))?;
+        }
+
+        let id = state.families.len();
+        let edit = VersionEdit {
+            family: id,
+            created_family: Some((name.to_string(), config.clone())),
+            oldest_wal: Some(writer.wal_id),
+            ..Default::default()
+        };
+        self.log_and_apply(&mut state, edit)?;
+
+        let version = Version {
+            memtable: Arc::new(WriteBuffer::new(writer.wal_id, config.memtable)),
+            immutable: Vec::new(),
+            levels: vec![Vec::new(); config.levels],
+            config: Arc::new(config),
+        };
+        self.versions.lock().unwrap().push(Arc::new(version));
+
+        Ok(id)
+    }
+
+    /// Looks `key` of `family` up as a reader of the writes up to `sequence`.
+    ///
+    /// Sources are checked from the newest one. A range tombstone never hides versions of newer
+    /// sources, so the first source with a value of the key, a tombstone or a range tombstone
+    /// covering it settles the key. Merge operands found on the way are applied to it.
+    fn get(&self, family: usize, key: &K, sequence: u64) -> Result<Option<V>, Box<dyn Error>> {
+        let version = self.current(family);
+        let mut lookup = Lookup::new(sequence, self.now());
+
+        let memtables = std::iter::once(&version.memtable).chain(version.immutable.iter().rev());
+
+        for memtable in memtables {
+            let deleted_at = deleted_at(&memtable.range_tombstones(), key, sequence);
+
+            if lookup.add(memtable.get(key).as_ref(), deleted_at) {
+                return self.finish(lookup, key);
+            }
+        }
+
+        let mut add = |ss_table: Table<K, V>| -> Result<bool, Box<dyn Error>> {
+            let deleted_at = deleted_at(ss_table.range_tombstones(), key, sequence);
+            Ok(lookup.add(ss_table.get(key)?.as_ref(), deleted_at))
+        };
+
+        for table in version.levels[0].iter().rev() {
+            if add(self.load_ss_table(&version.config, 0, &table.meta)?)? {
+                return self.finish(lookup, key);
+            }
+        }
+
+        for (level, tables) in version.levels.iter().enumerate().skip(1) {
+            let candidate = tables.partition_point(|table| &table.meta.last_key < key);
+
+            let Some(table) = tables.get(candidate) else {
+                continue;
+            };
+
+            if &table.meta.first_key > key {
+                continue;
+            }
+
+            if add(self.load_ss_table(&version.config, level, &table.meta)?)? {
+                return self.finish(lookup, key);
+            }
+        }
+
+        self.finish(lookup, key)
+    }
+
+    fn finish(&self, lookup: Lookup<V>, key: &K) -> Result<Option<V>, Box<dyn Error>> {
+        lookup.finish(key, self.merge_operator().as_deref())
+    }
+
+    fn merge_operator(&self) -> Option<Arc<dyn MergeOperator<K, V>>> {
+        self.merge_operator.read().unwrap().clone()
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.read().unwrap().now()
+    }
+
+    fn range(
+        &self,
+        family: usize,
+        range: impl RangeBounds<K>,
+        sequence: u64,
+    ) -> Result<RangeIter<K, V>, Box<dyn Error>> {
+        let version = self.current(family);
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        let mut sources: Vec<Source<K, V>> = Vec::new();
+        let mut range_tombstones = Vec::new();
+
+        // Some memtables panic on such bounds, and no source has anything within them anyway.
+        if is_empty_range(&bounds) {
+            return Ok(RangeIter::new(
+                sources,
+                bounds.1,
+                sequence,
+                range_tombstones,
+                None,
+                self.now(),
+            ));
+        }
+
+        let memtables = std::iter::once(&version.memtable).chain(version.immutable.iter().rev());
+
+        for memtable in memtables {
+            let pairs = memtable.range(bounds.clone());
+            sources.push(Box::new(pairs.into_iter().map(Ok)));
+            range_tombstones.extend(memtable.range_tombstones());
+        }
+
+        let level_0 = version.levels[0].iter().rev();
+        let other_levels = version.levels.iter().skip(1).flatten();
+
+        for table in level_0.chain(other_levels) {
+            if table.meta.overlaps(&bounds) {
+                let ss_table = self.load_ss_table(&version.config, table.level, &table.meta)?;
+                sources.push(Box::new(ss_table.range(bounds.clone())?));
+                range_tombstones.extend_from_slice(ss_table.range_tombstones());
+            }
+        }
+
+        Ok(RangeIter::new(
+            sources,
+            bounds.1,
+            sequence,
+            range_tombstones,
+            self.merge_operator(),
+            self.now(),
+        ))
+    }
+
+    /// Appends the changes of a write to the WAL as one record and to the active memtables of
+    /// their column families. When the changes of a family don't fit into its memtable, it's made
+    /// immutable first, so a write is never split between memtables of a family. All full
+    /// memtables are rotated together, so a write switches to a new WAL at most once.
+    fn write(
+        &self,
+        writer: &mut Writer<K, V>,
+        changes: Vec<Changes<K, V>>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_background_error()?;
+
+        let families = self.versions.lock().unwrap().len();
+        let mut sync = false;
+        let mut full_families = Vec::new();
+
+        for changes in &changes {
+            if changes.family >= families {
+                return Err("LSM tree has no such column family".into());
+            }
+
+            let version = self.current(changes.family);
+            sync |= version.config.sync_writes;
+
+            let memtable = &version.memtable;
+            let count = changes.pairs.len() + changes.deleted_ranges.len();
+            let size = memtable::encoded_size(changes);
+
+            let full = memtable.len() + count > version.config.memtable_size
+                || memtable.size() + size > version.config.memtable_max_bytes;
+
+            if !memtable.is_empty() && full {
+                full_families.push(changes.family);
+            }
+        }
+
+        if !full_families.is_empty() {
+            self.rotate_memtables(writer, &full_families)?;
+        }
+
+        let record = WalRecord {
+            sequence: self.last_sequence.load(Ordering::SeqCst) + 1,
+            changes,
+        };
+        writer.wal.append(&record)?;
+
+        if sync {
+            writer.wal.sync()?;
+        }
+
+        let sequence = record.sequence;
+
+        for changes in record.changes {
+            self.current(changes.family).memtable.insert(
+                sequence,
+                changes.pairs,
+                changes.deleted_ranges,
+            );
+        }
+
+        // Only now the write becomes visible to new snapshots.
+        self.last_sequence.store(sequence, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Replaces the active memtables of `families` with empty ones, and hands the old ones over
+    /// to the flush thread. New writes of all families go to a new WAL from now on, so the WALs of
+    /// the old memtables may be removed once the memtables are flushed, and empty memtables of the
+    /// other families start over with it. Waits while too many memtables of the families are
+    /// waiting for a flush.
+    fn rotate_memtables(
+        &self,
+        writer: &mut Writer<K, V>,
+        families: &[usize],
+    ) -> Result<(), Box<dyn Error>> {
+        // Taken before the version, as the state is never locked while holding the version.
+        let wal_id = self.state.lock().unwrap().new_file_number();
+
+        let mut versions = self.versions.lock().unwrap();
+
+        while families.iter().any(|&family| {
+            versions[family].immutable.len() >= versions[family].config.max_immutable_memtables
+        }) {
+            self.check_background_error()?;
+            versions = self.version_changed.wait(versions).unwrap();
+        }
+
+        writer.wal = Wal::create(&wal_path(&self.data_directory, wal_id))?;
+        writer.wal_id = wal_id;
+
+        // Synced writes to the new WAL would be lost along with its directory entry otherwise.
+        if versions.iter().any(|version| version.config.sync_writes) {
+            sync_wal_directory(&self.data_directory)?;
+        }
+
+        for family in 0..versions.len() {
+            let version = &versions[family];
+            let mut immutable = version.immutable.clone();
+
+            // Empty memtables of the other families move to the new WAL as well, so the old WALs
+            // may go once the rest is flushed.
+            if families.contains(&family) {
+                immutable.push(version.memtable.clone());
+            } else if !version.memtable.is_empty() {
+                continue;
+            }
+
+            versions[family] = Arc::new(Version {
+                config: version.config.clone(),
+                memtable: Arc::new(WriteBuffer::new(wal_id, version.config.memtable)),
+                immutable,
+                levels: version.levels.clone(),
+            });
+        }
+
+        self.version_changed.notify_all();
+
+        Ok(())
+    }
+
+    /// Makes the active memtables of `families` immutable and waits until all their memtables
+    /// are written into level0 SsTables.
+    fn flush(&self, families: &[usize]) -> Result<(), Box<dyn Error>> {
+        {
+            let mut writer = self.writer.lock().unwrap();
+
+            let filled: Vec<_> = families
+                .iter()
+                .copied()
+                .filter(|&family| !self.current(family).memtable.is_empty())
+                .collect();
+
+            if !filled.is_empty() {
+                self.rotate_memtables(&mut writer, &filled)?;
+            }
+        }
+
+        let mut versions = self.versions.lock().unwrap();
+
+        while families
+            .iter()
+            .any(|&family| !versions[family].immutable.is_empty())
+        {
+            self.check_background_error()?;
+            versions = self.version_changed.wait(versions).unwrap();
+        }
+
+        self.check_background_error()
+    }
+
+    /// Body of the flush thread: writes immutable memtables into level0, from the oldest one of
+    /// each column family.
+    fn run_flushes(&self) {
+        loop {
+            let (family, memtable) = {
+                let mut versions = self.versions.lock().unwrap();
+
+                loop {
+                    let oldest = versions.iter().enumerate().find_map(|(family, version)| {
+                        Some((family, version.immutable.first()?.clone()))
+                    });
+
+                    if let Some(oldest) = oldest {
+                        break oldest;
+                    }
+
+                    if self.shutdown.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    versions = self.version_changed.wait(versions).unwrap();
+                }
+            };
+
+            if let Err(e) = self.flush_memtable(family, &memtable) {
+                self.fail(e);
+                return;
+            }
+        }
+    }
+
+    fn flush_memtable(
+        &self,
+        family: usize,
+        memtable: &WriteBuffer<K, V>,
+    ) -> Result<(), Box<dyn Error>> {
+        let version = self.current(family);
+        let mut map = memtable.to_map();
+        let range_tombstones = memtable.range_tombstones();
+        let snapshots = self.live_snapshots();
+        let merge_operator = self.merge_operator();
+        let now = self.now();
+
+        for (key, history) in &mut map {
+            let deleted_at = covering(&range_tombstones, key);
+            history.expire(now);
+            history.retain_visible(&snapshots, &deleted_at);
+
+            // Older SsTables may hold the value operands apply to, unless a range tombstone hides
+            // it.
+            if let Some(operator) = &merge_operator
+                && snapshots.is_empty()
+            {
+                history.fold_operands(key, operator.as_ref(), !deleted_at.is_empty());
+            }
+        }
+
+        // Versions hidden by range tombstones of the memtable may be all a key has.
+        map.retain(|_, history| !history.is_empty());
+
+        let table = self.write_ss_table(&version.config, 0, map, range_tombstones)?;
+        reach(&self.data_directory, Step::FlushTableWritten)?;
+
+        // Writes of the family in WALs older than the next memtable are all in the new table.
+        let next_memtable = version.immutable.get(1).unwrap_or(&version.memtable);
+
+        let mut state = self.state.lock().unwrap();
+
+        // Other families with no memtable waiting for a flush have all their unflushed writes in
+        // WALs from the one their active memtable started with.
+        let idle_oldest_wals = (0..state.families.len())
+            .filter(|&other| other != family)
+            .filter_map(|other| {
+                let version = self.current(other);
+                let oldest_wal = version.memtable.wal_id;
+                let idle =
+                    version.immutable.is_empty() && oldest_wal > state.families[other].oldest_wal;
+
+                idle.then_some((other, oldest_wal))
+            })
+            .collect();
+
+        let edit = VersionEdit {
+            family,
+            oldest_wal: Some(next_memtable.wal_id),
+            idle_oldest_wals,
+            last_sequence: Some(state.last_sequence.max(table.largest_sequence)),
+            added_tables: vec![(0, table)],
+            ..Default::default()
+        };
+        self.log_and_apply(&mut state, edit)?;
+        reach(&self.data_directory, Step::FlushEditLogged)?;
+
+        // WALs holding only flushed writes of all families aren't needed anymore.
+        for id in list_wals(&self.data_directory)? {
+            if id < state.oldest_wal() {
+                std::fs::remove_file(wal_path(&self.data_directory, id))?;
+            }
+        }
+        reach(&self.data_directory, Step::FlushWalsRemoved)?;
+
+        self.install(family, &state, |version| {
+            version.immutable.remove(0);
+        });
+        self.state_changed.notify_all();
+
+        Ok(())
+    }
+
+    /// Body of the compaction thread: compacts a column family whenever its level0 or any other
+    /// level grows over its limit. Compactions which are due are done even when the tree is being
+    /// closed.
+    fn run_compactions(&self) {
+        loop {
+            let family = {
+                let mut state = self.state.lock().unwrap();
+
+                loop {
+                    let due = state
+                        .families
+                        .iter()
+                        .position(|family| family.needs_compaction());
+
+                    if let Some(family) = due {
+                        break family;
+                    }
+
+                    if self.shutdown.load(Ordering::SeqCst)
+                        || self.check_background_error().is_err()
+                    {
+                        return;
+                    }
+
+                    state = self.state_changed.wait(state).unwrap();
+                }
+            };
+
+            if let Err(e) = self.compact(family) {
+                self.fail(e);
+                return;
+            }
+        }
+    }
+
+    fn compact(&self, family: usize) -> Result<(), Box<dyn Error>> {
+        let _compaction = self.compaction.lock().unwrap();
+
+        let (level_0, levels) = {
+            let state = self.state.lock().unwrap();
+            let family = &state.families[family];
+            (family.levels[0].clone(), family.config.levels)
+        };
+
+        if !level_0.is_empty() {
+            self.compact_tables(family, 0, level_0)?;
+        }
+
+        for level in 1..levels - 1 {
+            loop {
+                let table = {
+                    let mut state = self.state.lock().unwrap();
+                    let family = &mut state.families[family];
+
+                    if family.level_size(level) <= family.max_level_size(level) {
+                        break;
+                    }
+
+                    family.pick_table_to_compact(level)
+                };
+
+                self.compact_tables(family, level, vec![table])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges `tables` of `level` of `family` with the overlapping tables of the next level and
+    /// replaces all of them with the merged tables in the next level.
+    fn compact_tables(
+        &self,
+        family: usize,
+        level: usize,
+        tables: Vec<TableMeta<K>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let next_level = level + 1;
+
+        let first_key = tables.iter().map(|table| &table.first_key).min().cloned();
+        let last_key = tables.iter().map(|table| &table.last_key).max().cloned();
+        let (Some(first_key), Some(last_key)) = (first_key, last_key) else {
+            return Ok(());
+        };
+
+        // Only compaction changes levels below level0, so they stay as they are until the result
+        // is installed.
+        let layout = self.state.lock().unwrap().families[family].clone();
+
+        let overlapping: Vec<_> = layout.levels[next_level]
+            .iter()
+            .filter(|table| table.overlaps(&(first_key.clone()..=last_key.clone())))
+            .cloned()
+            .collect();
+
+        // Sources go from the newest to the oldest: level0 tables are ordered from the oldest,
+        // while tables of the next level are older than any table of this level.
+        let snapshots = self.live_snapshots();
+        let merge_operator = self.merge_operator();
+        let now = self.now();
+        let mut sources: Vec<Source<K, V>> = Vec::new();
+        let fill_cache = layout.config.compaction_fills_block_cache;
+
+        let mut range_tombstones = Vec::new();
+        let inputs = tables
+            .iter()
+            .rev()
+            .map(|table| (level, table))
+            .chain(overlapping.iter().map(|table| (next_level, table)));
+
+        for (level, table) in inputs {
+            let ss_table = self.load_ss_table(&layout.config, level, table)?;
+            range_tombstones.extend_from_slice(ss_table.range_tombstones());
+            sources.push(Box::new(ss_table.iter()?.fill_cache(fill_cache)));
+        }
+
+        // A range tombstone is needed while a snapshot may see versions written before it, or
+        // deeper levels may hold keys it covers. Versions it hides are dropped here anyway.
+        let mut pending = range_tombstones.clone();
+        pending.retain(|tombstone| {
+            let needed = snapshots
+                .iter()
+                .any(|snapshot| *snapshot < tombstone.sequence)
+                || layout.may_overlap_below(next_level, &tombstone.start, &tombstone.end);
+
+            if !needed {
+                self.purged_tombstones.fetch_add(1, Ordering::SeqCst);
+            }
+
+            needed
+        });
+        pending.sort_by(|a, b| b.start.cmp(&a.start));
+
+        let mut added_tables = Vec::new();
+        let mut chunk = BTreeMap::new();
+        let mut chunk_tombstones: Vec<RangeTombstone<K>> = Vec::new();
+        let mut chunk_size = 0;
+
+        for pair in MergeIter::new(sources) {
+            let (key, mut history) = pair?;
+
+            let deleted_at = covering(&range_tombstones, &key);
+            history.expire(now);
+            history.retain_visible(&snapshots, &deleted_at);
+
+            // Operands are folded only when no snapshot may need the versions in between.
+            if let Some(operator) = &merge_operator
+                && snapshots.is_empty()
+            {
+                let base_known =
+                    !deleted_at.is_empty() || !layout.may_contain_below(next_level, &key);
+                history.fold_operands(&key, operator.as_ref(), base_known);
+            }
+
+            // A tombstone only has to shadow older versions of its key. Once none of the deeper
+            // levels may hold the key, the oldest tombstone has nothing left to hide.
+            if !layout.may_contain_below(next_level, &key) {
+                while history.pop_oldest_tombstone() {
+                    self.purged_tombstones.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+
+            if history.is_empty() {
+                continue;
+            }
+
+            while let Some(tombstone) = pending.pop_if(|tombstone| tombstone.start < key) {
+                chunk_size +=
+                    bincode::encode_to_vec(&tombstone, bincode::config::standard())?.len() as u64;
+                chunk_tombstones.push(tombstone);
+            }
+
+            // Tables of a level never overlap, so a table may end only where none of its range
+            // tombstones reaches the next key.
+            if !chunk.is_empty()
+                && chunk_size >= layout.config.ss_table_target_size
+                && chunk_tombstones.iter().all(|tombstone| tombstone.end < key)
+            {
+                let table = self.write_ss_table(
+                    &layout.config,
+                    next_level,
+                    std::mem::take(&mut chunk),
+                    std::mem::take(&mut chunk_tombstones),
+                )?;
+                added_tables.push(table);
+                reach(&self.data_directory, Step::CompactionTableWritten)?;
+                chunk_size = 0;
+            }
+
+            chunk_size +=
+                bincode::encode_to_vec((&key, &history), bincode::config::standard())?.len() as u64;
+            chunk.insert(key, history);
+        }
+
+        chunk_tombstones.extend(pending.into_iter().rev());
+
+        if !chunk.is_empty() || !chunk_tombstones.is_empty() {
+            let table = self.write_ss_table(&layout.config, next_level, chunk, chunk_tombstones)?;
+            added_tables.push(table);
+            reach(&self.data_directory, Step::CompactionTableWritten)?;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        let removed_tables = tables
+            .iter()
+            .map(|table| (level, table.id))
+            .chain(overlapping.iter().map(|table| (next_level, table.id)))
+            .collect();
+
+        let compaction_pointers = state.families[family].compaction_pointers[level]
+            .clone()
+            .map(|pointer| (level, pointer))
+            .into_iter()
+            .collect();
+
+        let edit = VersionEdit {
+            family,
+            added_tables: added_tables
+                .into_iter()
+                .map(|table| (next_level, table))
+                .collect(),
+            removed_tables,
+            compaction_pointers,
+            ..Default::default()
+        };
+        self.log_and_apply(&mut state, edit)?;
+        reach(&self.data_directory, Step::CompactionEditLogged)?;
+
+        // Replaced tables are removed once no reader refers to them anymore.
+        self.install(family, &state, |_| {});
+        self.state_changed.notify_all();
+
+        Ok(())
+    }
+
+    /// Installs a new version of `family` whose tables are the ones of `state`, changed by
+    /// `change`. Tables which are not part of the family anymore are marked obsolete.
+    fn install(&self, family: usize, state: &State<K>, change: impl FnOnce(&mut Version<K, V>)) {
+        let mut versions = self.versions.lock().unwrap();
+        let version = &versions[family];
+
+        let mut tables: HashMap<_, _> = version
+            .levels
+            .iter()
+            .flatten()
+            .map(|table| (table.meta.id, table.clone()))
+            .collect();
+
+        let levels = state.families[family]
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, metas)| {
+                metas
+                    .iter()
+                    .map(|meta| {
+                        tables.remove(&meta.id).unwrap_or_else(|| {
+                            let path = ss_table_path(&self.data_directory, level, meta.id);
+                            let table =
+                                TableFile::new(level, meta.clone(), path, self.table_cache.clone());
+                            Arc::new(table)
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for table in tables.values() {
+            table.mark_obsolete();
+        }
+
+        let mut new_version = Version {
+            config: version.config.clone(),
+            memtable: version.memtable.clone(),
+            immutable: version.immutable.clone(),
+            levels,
+        };
+        change(&mut new_version);
+
+        let old_version = std::mem::replace(&mut versions[family], Arc::new(new_version));
+        self.version_changed.notify_all();
+
+        // Dropping the old version may remove files, which is better done without the lock.
+        drop(versions);
+        drop(old_version);
+    }
+
+    /// Sequence numbers of all live snapshots, from the oldest.
+    fn live_snapshots(&self) -> Vec<u64> {
+        self.snapshots.lock().unwrap().keys().copied().collect()
+    }
+
+    fn release_snapshot(&self, sequence: u64) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+
+        if let Some(count) = snapshots.get_mut(&sequence) {
+            *count -= 1;
+
+            if *count == 0 {
+                snapshots.remove(&sequence);
+            }
+        }
+    }
+
+    fn fail(&self, error: Box<dyn Error>) {
+        self.background_error
+            .lock()
+            .unwrap()
+            .get_or_insert(error.to_string());
+
+        // Wakes up everybody waiting for the background threads, so they see the error.
+        drop(self.versions.lock().unwrap());
+        self.version_changed.notify_all();
+        drop(self.state.lock().unwrap());
+        self.state_changed.notify_all();
+    }
+
+    fn check_background_error(&self) -> Result<(), Box<dyn Error>> {
+        match &*self.background_error.lock().unwrap() {
+            Some(error) => Err(format!("background work of LSM tree failed: {error}").into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes `map` and `range_tombstones`, not both empty, into a new SsTable of `level`, tuned
+    /// by `config` of its column family. The key range of the table covers its range tombstones as
+    /// well.
+    fn write_ss_table(
+        &self,
+        config: &LsmTreeConfig,
+        level: usize,
+        map: BTreeMap<K, History<V>>,
+        range_tombstones: Vec<RangeTombstone<K>>,
+    ) -> Result<TableMeta<K>, Box<dyn Error>> {
+        let id = self.state.lock().unwrap().new_file_number();
+
+        let first_keys = map.keys().next().into_iter();
+        let first_key = first_keys
+            .chain(range_tombstones.iter().map(|tombstone| &tombstone.start))
+            .min()
+            .cloned();
+
+        let last_keys = map.keys().next_back().into_iter();
+        let last_key = last_keys
+            .chain(range_tombstones.iter().map(|tombstone| &tombstone.end))
+            .max()
+            .cloned();
+
+        let (Some(first_key), Some(last_key)) = (first_key, last_key) else {
+            return Err("SsTable can't be empty".into());
+        };
+
+        let sequences = range_tombstones.iter().map(|tombstone| tombstone.sequence);
+        let smallest_sequence = map
+            .values()
+            .map(History::first_sequence)
+            .chain(sequences.clone())
+            .min();
+        let largest_sequence = map
+            .values()
+            .map(History::last_sequence)
+            .chain(sequences)
+            .max();
+
+        let path = ss_table_path(&self.data_directory, level, id);
+        let ss_table = SsTable::with_range_tombstones(
+            map,
+            range_tombstones,
+            &path,
+            &config.ss_table_options(),
+        )?;
+        let ss_table = self.open_ss_table(config, id, ss_table);
+        let size = ss_table.size()?;
+
+        // A new table is likely to be read soon, so it goes to the cache right away.
+        self.table_cache
+            .lock()
+            .unwrap()
+            .insert(id, Arc::new(ss_table));
+
+        Ok(TableMeta {
+            id,
+            first_key,
+            last_key,
+            size,
+            smallest_sequence: smallest_sequence.unwrap_or(0),
+            largest_sequence: largest_sequence.unwrap_or(0),
+        })
+    }
+
+    /// Sets up `ss_table` to read through the caches of the tree, as `config` of its column family
+    /// asks.
+    fn open_ss_table(
+        &self,
+        config: &LsmTreeConfig,
+        id: u64,
+        ss_table: SsTable<K, History<V>>,
+    ) -> SsTable<K, History<V>> {
+        let ss_table = ss_table.with_block_cache(id, self.block_cache.clone());
+
+        if config.pin_index_partitions {
+            ss_table.pin_index_partitions()
+        } else {
+            ss_table
+        }
+    }
+
+    /// Returns the SsTable from the table cache, loading it from disk for the column family with
+    /// `config` if it's not there.
+    fn load_ss_table(
+        &self,
+        config: &LsmTreeConfig,
+        level: usize,
+        table: &TableMeta<K>,
+    ) -> Result<Table<K, V>, Box<dyn Error>> {
+        if let Some(ss_table) = self.table_cache.lock().unwrap().get(&table.id) {
+            return Ok(ss_table.clone());
+        }
+
+        let ss_table = SsTable::load(ss_table_path(&self.data_directory, level, table.id))?;
+        let ss_table = Arc::new(self.open_ss_table(config, table.id, ss_table));
+
+        self.table_cache
+            .lock()
+            .unwrap()
+            .insert(table.id, ss_table.clone());
+
+        Ok(ss_table)
+    }
+
+    /// Persists `edit` in the manifest and applies it to `state`. The edit also records the number
+    /// of the next file, so numbers handed out before it are never reused.
+    fn log_and_apply(
+        &self,
+        state: &mut State<K>,
+        mut edit: VersionEdit<K>,
+    ) -> Result<(), Box<dyn Error>> {
+        edit.next_file = Some(state.next_file);
+
+        self.manifest.lock().unwrap().append(&edit)?;
+        state.apply(edit);
+
+        Ok(())
+    }
+}
+
+fn ss_table_path(data_directory: &str, level: usize, id: u64) -> String {
+    format!("{data_directory}/level{level}/{id}")
+}
+
+fn wal_path(data_directory: &str, id: u64) -> String {
+    format!("{data_directory}/wal/{id}")
+}
+
+/// Forces the entries of newly created WALs to the disk, so the WALs survive a crash of the
+/// machine.
+fn sync_wal_directory(data_directory: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::File::open(format!("{data_directory}/wal"))?.sync_all()?;
+
+    Ok(())
+}
+
+/// Ids of the WALs of the tree in `data_directory`. Other files in the WAL directory, such as
+/// leftovers of tools or editors, are ignored.
+fn list_wals(data_directory: &str) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut ids = Vec::new();
+
+    for entry in std::fs::read_dir(format!("{data_directory}/wal"))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+
+        if let Some(id) = manifest::file_number(&name).filter(|id| id.to_string() == name) {
+            ids.push(id);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Sequence number of the newest range tombstone covering `key` which a reader of the writes up to
+/// `sequence` sees.
+fn deleted_at<K>(range_tombstones: &[RangeTombstone<K>], key: &K, sequence: u64) -> Option<u64>
+where
+    K: Ord,
+{
+    range_tombstones
+        .iter()
+        .filter(|tombstone| tombstone.sequence <= sequence && tombstone.covers(key))
+        .map(|tombstone| tombstone.sequence)
+        .max()
+}
+
+/// Whether no key is within `bounds`: the start is past the end, or both are the same key and
+/// one of them excludes it.
+fn is_empty_range<K>(bounds: &(Bound<K>, Bound<K>)) -> bool
+where
+    K: Ord,
+{
+    match bounds {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start >= end,
+        _ => false,
+    }
+}
+
+/// Sequence numbers of all range tombstones covering `key`.
+fn covering<K>(range_tombstones: &[RangeTombstone<K>], key: &K) -> Vec<u64>
+where
+    K: Ord,
+{
+    range_tombstones
+        .iter()
+        .filter(|tombstone| tombstone.covers(key))
+        .map(|tombstone| tombstone.sequence)
+        .collect()
 }
@@ -0,0 +1,93 @@
+use crate::{
+    lsm_tree::{
+        MergeOperator, deleted_at,
+        history::Lookup,
+        merge_iter::{MergeIter, Source},
+    },
+    sstable::RangeTombstone,
+};
+use std::{error::Error, ops::Bound, sync::Arc};
+
+/// Ordered iterator over the live key-value pairs of an [`LsmTree`](crate::lsm_tree::LsmTree)
+/// which fall into a range of keys.
+///
+/// Merges the memtables and all SsTables which may hold keys of the range. Only the versions of a
+/// key written up to the sequence number of the reader are taken into account: merge operands are
+/// applied to the newest value below them, and keys whose newest version is a tombstone, has
+/// expired or is older than a range tombstone covering the key are skipped.
+pub struct RangeIter<K, V>
+where
+    V: Clone,
+{
+    merged: MergeIter<'static, K, V>,
+    end: Bound<K>,
+    sequence: u64,
+    /// Range tombstones the reader sees, of all sources of the iterator.
+    range_tombstones: Vec<RangeTombstone<K>>,
+    merge_operator: Option<Arc<dyn MergeOperator<K, V>>>,
+    /// Time of the read, in milliseconds since the Unix epoch.
+    now: u64,
+}
+
+impl<K, V> RangeIter<K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    pub(super) fn new(
+        sources: Vec<Source<'static, K, V>>,
+        end: Bound<K>,
+        sequence: u64,
+        mut range_tombstones: Vec<RangeTombstone<K>>,
+        merge_operator: Option<Arc<dyn MergeOperator<K, V>>>,
+        now: u64,
+    ) -> Self {
+        range_tombstones.retain(|tombstone| tombstone.sequence <= sequence);
+
+        Self {
+            merged: MergeIter::new(sources),
+            end,
+            sequence,
+            range_tombstones,
+            merge_operator,
+            now,
+        }
+    }
+}
+
+impl<K, V> Iterator for RangeIter<K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    type Item = Result<(K, V), Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, history) = match self.merged.next()? {
+                Ok(pair) => pair,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let after_end = match &self.end {
+                Bound::Included(end) => &key > end,
+                Bound::Excluded(end) => &key >= end,
+                Bound::Unbounded => false,
+            };
+
+            if after_end {
+                return None;
+            }
+
+            let mut lookup = Lookup::new(self.sequence, self.now);
+            let deleted_at = deleted_at(&self.range_tombstones, &key, self.sequence);
+            lookup.add(Some(&history), deleted_at);
+
+            match lookup.finish(&key, self.merge_operator.as_deref()) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::lsm_tree::{ColumnFamily, DEFAULT_FAMILY, LsmTree, RangeIter};
+use std::{error::Error, hash::Hash, ops::RangeBounds};
+
+/// Read-only view of an [`LsmTree`] as it was when the snapshot was taken.
+///
+/// Plain reads go to the default column family, while the `_cf` variants read any other family as
+/// of the same moment.
+///
+/// Writes made after that are invisible to the snapshot. Flushes and compactions keep the versions
+/// of keys the snapshot sees for as long as it's alive.
+pub struct Snapshot<'a, K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    tree: &'a LsmTree<K, V>,
+    sequence: u64,
+}
+
+impl<'a, K, V> Snapshot<'a, K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    pub(super) fn new(tree: &'a LsmTree<K, V>, sequence: u64) -> Self {
+        Self { tree, sequence }
+    }
+
+    /// Sequence number of the last write the snapshot sees.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
+        self.tree.inner.get(DEFAULT_FAMILY, key, self.sequence)
+    }
+
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<RangeIter<K, V>, Box<dyn Error>> {
+        self.tree.inner.range(DEFAULT_FAMILY, range, self.sequence)
+    }
+
+    pub fn get_cf(
+        &self,
+        family: &ColumnFamily<K, V>,
+        key: &K,
+    ) -> Result<Option<V>, Box<dyn Error>> {
+        self.tree.inner.get(family.id(), key, self.sequence)
+    }
+
+    pub fn range_cf(
+        &self,
+        family: &ColumnFamily<K, V>,
+        range: impl RangeBounds<K>,
+    ) -> Result<RangeIter<K, V>, Box<dyn Error>> {
+        self.tree.inner.range(family.id(), range, self.sequence)
+    }
+}
+
+impl<K, V> Drop for Snapshot<'_, K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        self.tree.inner.release_snapshot(self.sequence);
+    }
+}
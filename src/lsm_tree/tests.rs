@@ -1,4 +1,18 @@
-use crate::lsm_tree::LsmTree;
+use crate::{
+    lsm_tree::{
+        CRASH_AT, Clock, DEFAULT_FAMILY, FamilyState, LsmTree, LsmTreeConfig, MemtableKind,
+        MergeOperator, Step, Value, WriteBatch, history::History,
+    },
+    sstable::{BloomFilterPolicy, SsTable},
+};
+use std::{
+    ops::Bound,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 #[test]
 fn test_initialization_creates_empty_directory() {
@@ -8,23 +22,24 @@ fn test_initialization_creates_empty_directory() {
 
     let _ = lsm_three("test_initialization_creates_empty_directory");
 
-    let expected_content = vec![
-        format!("{path}/state"),
-        format!("{path}/level0"),
-        format!("{path}/level1"),
-    ];
+    let mut expected_content = vec![format!("{path}/CURRENT"), format!("{path}/MANIFEST-1")];
+    expected_content
+        .extend((0..LsmTreeConfig::default().levels).map(|level| format!("{path}/level{level}")));
+    expected_content.push(format!("{path}/wal"));
 
-    let actual_content: Vec<_> = std::fs::read_dir(path)
+    let mut actual_content: Vec<_> = std::fs::read_dir(path)
         .unwrap()
         .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
         .collect();
 
+    actual_content.sort();
+
     assert_eq!(actual_content, expected_content);
 }
 
 #[test]
 fn test_simple_insert_and_get() {
-    let mut tree = lsm_three("test_simple_insert_and_get");
+    let tree = lsm_three("test_simple_insert_and_get");
 
     let key = "Hello".to_string();
     let value = "World".to_string();
@@ -38,7 +53,7 @@ fn test_simple_insert_and_get() {
 
 #[test]
 fn test_memtable_never_exceeds_configured_size_while_all_data_is_accessible() {
-    let mut tree =
+    let tree =
         lsm_three("test_memtable_never_exceeds_configured_size_while_all_data_is_accessible");
 
     for i in 0..1000 {
@@ -46,7 +61,7 @@ fn test_memtable_never_exceeds_configured_size_while_all_data_is_accessible() {
             .unwrap();
     }
 
-    assert_eq!(tree.map.len(), 100);
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 100);
 
     for i in 0..1000 {
         let value = tree.get(&format!("key_{i}")).unwrap();
@@ -57,7 +72,7 @@ fn test_memtable_never_exceeds_configured_size_while_all_data_is_accessible() {
 
 #[test]
 fn test_no_reads_in_unrequired_ss_tables() {
-    let mut tree = lsm_three("test_no_reads_in_unrequired_ss_tables");
+    let tree = lsm_three("test_no_reads_in_unrequired_ss_tables");
 
     for i in 0..800 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -71,23 +86,23 @@ fn test_no_reads_in_unrequired_ss_tables() {
     // (despite the fact that some SSTable's inner bloom filter can return false-positive).
     let key = "key_18".to_string();
 
-    for table in 1..8 {
-        std::fs::remove_file(format!(
-            "target/test_no_reads_in_unrequired_ss_tables/level0/{table}.data"
-        ))
-        .unwrap();
+    for table in &state(&tree).levels[0][1..] {
+        corrupt_data_blocks(&format!(
+            "target/test_no_reads_in_unrequired_ss_tables/level0/{}",
+            table.id
+        ));
     }
 
     assert!(tree.get(&key).unwrap().is_some());
 
-    // Just to ensure that other ss tables are deleted
+    // Just to ensure that other ss tables are corrupted
     let key = "key_700".to_string();
     assert!(tree.get(&key).is_err());
 }
 
 #[test]
 fn load_tree() {
-    let mut tree = lsm_three("load_tree");
+    let tree = lsm_three("load_tree");
 
     for i in 0..800 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -105,7 +120,7 @@ fn load_tree() {
 
 #[test]
 fn test_compaction_moves_data_to_level_1() {
-    let mut tree = lsm_three("test_compaction_moves_data_to_level_1");
+    let tree = lsm_three("test_compaction_moves_data_to_level_1");
 
     // Produces 5 saved SS Tables, so 15 files
     for i in 0..500 {
@@ -127,13 +142,13 @@ fn test_compaction_moves_data_to_level_1() {
         std::fs::read_dir("target/test_compaction_moves_data_to_level_1/level1")
             .unwrap()
             .count(),
-        3 // for idx, filter and data
+        1
     );
 }
 
 #[test]
 fn test_after_compaction_data_is_still_accessible() {
-    let mut tree = lsm_three("test_after_compaction_data_is_still_accessible");
+    let tree = lsm_three("test_after_compaction_data_is_still_accessible");
 
     for i in 0..500 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -148,7 +163,7 @@ fn test_after_compaction_data_is_still_accessible() {
 
 #[test]
 fn test_compaction_leaves_more_recent_key_value() {
-    let mut tree = lsm_three("test_compaction_leaves_more_recent_key_value");
+    let tree = lsm_three("test_compaction_leaves_more_recent_key_value");
 
     for i in 0..5 {
         tree.insert("key".to_string(), format!("v{i}")).unwrap();
@@ -164,7 +179,7 @@ fn test_compaction_leaves_more_recent_key_value() {
 
 #[test]
 fn test_delete_if_key_exists() {
-    let mut tree = lsm_three("test_delete_if_key_exists");
+    let tree = lsm_three("test_delete_if_key_exists");
 
     for i in 0..1500 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -183,7 +198,7 @@ fn test_delete_if_key_exists() {
 
 #[test]
 fn test_skip_if_key_does_not_exist() {
-    let mut tree = lsm_three("test_skip_if_key_does_not_exist");
+    let tree = lsm_three("test_skip_if_key_does_not_exist");
 
     for i in 0..1500 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -199,7 +214,7 @@ fn test_skip_if_key_does_not_exist() {
 
 #[test]
 fn test_compaction_works_with_deletion() {
-    let mut tree = lsm_three("test_compaction_works_with_deletion");
+    let tree = lsm_three("test_compaction_works_with_deletion");
 
     for i in 0..500 {
         tree.insert(format!("key_{i}"), format!("value_{i}"))
@@ -228,6 +243,2059 @@ fn test_compaction_works_with_deletion() {
     assert!(tree.get(&"some_value".to_string()).unwrap().is_none());
 }
 
-fn lsm_three(test_name: &str) -> LsmTree<String, String> {
-    LsmTree::new(format!("target/{test_name}"), 100, 10, 10).unwrap()
+#[test]
+fn test_unflushed_writes_survive_crash() {
+    let mut tree = lsm_three("test_unflushed_writes_survive_crash");
+
+    for i in 0..150 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    tree.delete("key_120".to_string()).unwrap();
+
+    // Simulates a killed process: the tree is never flushed by `Drop`.
+    tree.stop_background_work();
+    std::mem::forget(tree);
+
+    let tree =
+        LsmTree::<String, String>::load("target/test_unflushed_writes_survive_crash".to_string())
+            .unwrap();
+
+    // 50 unflushed inserts and the tombstone on top of `key_120`.
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 51);
+
+    assert_eq!(
+        tree.get(&"key_12".to_string()).unwrap(),
+        Some("value_12".to_string())
+    );
+    assert_eq!(
+        tree.get(&"key_149".to_string()).unwrap(),
+        Some("value_149".to_string())
+    );
+    assert_eq!(tree.get(&"key_120".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_stray_files_in_wal_directory_are_ignored() {
+    let name = "test_stray_files_in_wal_directory_are_ignored";
+    let tree = lsm_three(name);
+
+    for i in 0..50 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    drop(tree);
+
+    let strays = [".DS_Store", "notes.txt", "1.bak"];
+    for stray in strays {
+        std::fs::write(format!("target/{name}/wal/{stray}"), "stray").unwrap();
+    }
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    for i in 50..150 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    assert_eq!(tree.range(..).unwrap().count(), 150);
+    for stray in strays {
+        assert!(std::fs::exists(format!("target/{name}/wal/{stray}")).unwrap());
+    }
+}
+
+#[test]
+fn test_flush_truncates_wal() {
+    let tree = lsm_three("test_flush_truncates_wal");
+
+    for i in 0..50 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    let wal_size = || {
+        std::fs::read_dir("target/test_flush_truncates_wal/wal")
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum::<u64>()
+    };
+
+    assert!(wal_size() > 0);
+
+    tree.flush().unwrap();
+
+    assert_eq!(wal_size(), 0);
+}
+
+#[test]
+fn test_range_merges_memtable_and_all_levels() {
+    let tree = lsm_three("test_range_merges_memtable_and_all_levels");
+
+    for i in 0..300 {
+        tree.insert(format!("key_{i:03}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    // Newer versions in level0 and in the memtable shadow the ones in level1.
+    for i in (0..300).step_by(3) {
+        tree.insert(format!("key_{i:03}"), format!("new_value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    for i in (0..300).step_by(5) {
+        tree.insert(format!("key_{i:03}"), format!("newest_value_{i}"))
+            .unwrap();
+    }
+
+    tree.delete("key_101".to_string()).unwrap();
+
+    let pairs: Vec<_> = tree
+        .range("key_100".to_string().."key_111".to_string())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let expected: Vec<_> = (100..111)
+        .filter(|i| *i != 101)
+        .map(|i| {
+            let value = if i % 5 == 0 {
+                format!("newest_value_{i}")
+            } else if i % 3 == 0 {
+                format!("new_value_{i}")
+            } else {
+                format!("value_{i}")
+            };
+
+            (format!("key_{i:03}"), value)
+        })
+        .collect();
+
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn test_empty_and_inverted_ranges_have_no_keys() {
+    let tree = lsm_three("test_empty_and_inverted_ranges_have_no_keys");
+
+    for i in 0..150 {
+        tree.insert(format!("key_{i:03}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    let (a, b) = ("key_050".to_string(), "key_100".to_string());
+
+    assert_eq!(tree.range(b.clone()..a.clone()).unwrap().count(), 0);
+    assert_eq!(tree.range(b.clone()..=a.clone()).unwrap().count(), 0);
+    assert_eq!(tree.range(a.clone()..a.clone()).unwrap().count(), 0);
+    assert_eq!(
+        tree.range((Bound::Excluded(a.clone()), Bound::Excluded(a.clone())))
+            .unwrap()
+            .count(),
+        0
+    );
+    assert_eq!(
+        tree.range((Bound::Excluded(a.clone()), Bound::Included(a.clone())))
+            .unwrap()
+            .count(),
+        0
+    );
+    assert_eq!(tree.range(a.clone()..=a).unwrap().count(), 1);
+}
+
+#[test]
+fn test_range_hides_keys_deleted_in_newer_tables() {
+    let tree = lsm_three("test_range_hides_keys_deleted_in_newer_tables");
+
+    for i in 0..10 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    for i in (0..10).step_by(2) {
+        tree.delete(format!("key_{i}")).unwrap();
+    }
+    tree.flush().unwrap();
+
+    let keys: Vec<_> = tree
+        .range(..)
+        .unwrap()
+        .map(|pair| pair.unwrap().0)
+        .collect();
+
+    let expected: Vec<_> = (1..10).step_by(2).map(|i| format!("key_{i}")).collect();
+
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_prefix_iteration() {
+    let tree = lsm_three("test_prefix_iteration");
+
+    for tenant in ["a", "b", "c"] {
+        for i in 0..50 {
+            tree.insert(format!("{tenant}/{i:02}"), format!("value_{i}"))
+                .unwrap();
+        }
+    }
+
+    let keys: Vec<_> = tree
+        .prefix("b/".to_string())
+        .unwrap()
+        .map(|pair| pair.unwrap().0)
+        .collect();
+
+    let expected: Vec<_> = (0..50).map(|i| format!("b/{i:02}")).collect();
+
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_leveled_compaction_keeps_levels_sorted_and_bounded() {
+    let name = "test_leveled_compaction_keeps_levels_sorted_and_bounded";
+    let tree = leveled_lsm_three(name);
+
+    // Keys are scattered, so every flush overlaps with tables of all levels.
+    for i in 0..3000 {
+        let key = (i * 7919) % 3000;
+        tree.insert(format!("key_{key:04}"), format!("value_{key}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let state = state(&tree);
+
+    assert!(state.levels[0].is_empty());
+    assert!(!state.levels[3].is_empty());
+
+    for level in 1..state.levels.len() {
+        let tables = &state.levels[level];
+
+        for pair in tables.windows(2) {
+            assert!(pair[0].last_key < pair[1].first_key);
+        }
+
+        if level < state.levels.len() - 1 {
+            assert!(state.level_size(level) <= state.max_level_size(level));
+        }
+    }
+
+    for i in 0..3000 {
+        let value = tree.get(&format!("key_{i:04}")).unwrap();
+        assert_eq!(value, Some(format!("value_{i}")));
+    }
+
+    assert_eq!(tree.range(..).unwrap().count(), 3000);
+}
+
+#[test]
+fn test_compaction_rewrites_only_overlapping_tables() {
+    let name = "test_compaction_rewrites_only_overlapping_tables";
+    let tree = leveled_lsm_three(name);
+
+    for i in 0..200 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let level_1_before = state(&tree).levels[1].clone();
+    assert!(level_1_before.len() > 2);
+
+    let last_table = level_1_before.last().unwrap();
+    tree.insert(last_table.last_key.clone(), "updated".to_string())
+        .unwrap();
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let level_1_after = &state(&tree).levels[1];
+    assert_eq!(level_1_before.len(), level_1_after.len());
+
+    for (before, after) in level_1_before.iter().zip(level_1_after).rev().skip(1) {
+        assert_eq!(before.id, after.id);
+    }
+    assert_ne!(
+        level_1_before.last().unwrap().id,
+        level_1_after.last().unwrap().id
+    );
+
+    assert_eq!(
+        tree.get(&last_table.last_key).unwrap(),
+        Some("updated".to_string())
+    );
+}
+
+#[test]
+fn test_level_layout_is_persisted() {
+    let name = "test_level_layout_is_persisted";
+    let tree = leveled_lsm_three(name);
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+    drop(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+
+    assert_eq!(state(&tree).config.levels, 4);
+    assert!(
+        state(&tree)
+            .levels
+            .iter()
+            .skip(1)
+            .any(|tables| !tables.is_empty())
+    );
+
+    for i in 0..1000 {
+        let value = tree.get(&format!("key_{i:04}")).unwrap();
+        assert_eq!(value, Some(format!("value_{i}")));
+    }
+}
+
+#[test]
+fn test_compaction_purges_tombstones_in_bottom_level() {
+    let tree = lsm_three("test_compaction_purges_tombstones_in_bottom_level");
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    for i in 0..100 {
+        tree.delete(format!("key_{i}")).unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(tree.stats().purged_tombstones, 100);
+
+    let table = &state(&tree).levels[1][0];
+    let entries = tree
+        .inner
+        .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 1, table)
+        .unwrap()
+        .iter()
+        .unwrap()
+        .count();
+    assert_eq!(entries, 400);
+
+    assert_eq!(tree.get(&"key_42".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_compaction_keeps_tombstones_shadowing_deeper_levels() {
+    let name = "test_compaction_keeps_tombstones_shadowing_deeper_levels";
+    let tree = leveled_lsm_three(name);
+
+    for i in 0..3000 {
+        let key = (i * 7919) % 3000;
+        tree.insert(format!("key_{key:04}"), format!("value_{key}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let deep_key = state(&tree).levels[3][0].first_key.clone();
+
+    tree.delete(deep_key.clone()).unwrap();
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(tree.stats().purged_tombstones, 0);
+    assert_eq!(tree.get(&deep_key).unwrap(), None);
+}
+
+#[test]
+fn test_bloom_filter_policy_flows_into_ss_tables() {
+    let config = LsmTreeConfig {
+        ss_table_bloom_filter: BloomFilterPolicy::BitsPerKey(16),
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_bloom_filter_policy_flows_into_ss_tables", config);
+    tree.insert("key".to_string(), "value".to_string()).unwrap();
+    tree.flush().unwrap();
+
+    let table = &state(&tree).levels[0][0];
+    let ss_table = tree
+        .inner
+        .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 0, table)
+        .unwrap();
+
+    assert_eq!(
+        ss_table.properties().bloom_filter,
+        BloomFilterPolicy::BitsPerKey(16)
+    );
+}
+
+#[test]
+fn test_open_tables_are_limited_by_max_open_files() {
+    let config = LsmTreeConfig {
+        memtable_size: 100,
+        level_0_size: 100,
+        max_open_files: 2,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_open_tables_are_limited_by_max_open_files", config);
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    for i in 0..1000 {
+        let value = tree.get(&format!("key_{i}")).unwrap();
+
+        assert_eq!(value, Some(format!("value_{i}")));
+        assert!(tree.inner.table_cache.lock().unwrap().len() <= 2);
+    }
+}
+
+#[test]
+fn test_cached_tables_do_not_reload_meta_blocks() {
+    let name = "test_cached_tables_do_not_reload_meta_blocks";
+    let tree = lsm_three(name);
+
+    for i in 0..100 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    let misses = tree.stats().table_cache_misses;
+
+    // Only the data blocks are left intact, so the table can't be loaded from disk anymore.
+    let table = state(&tree).levels[0][0].clone();
+    let table_path = format!("target/{name}/level0/{}", table.id);
+    let data_size = tree
+        .inner
+        .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 0, &table)
+        .unwrap()
+        .properties()
+        .data_size as usize;
+    let mut data = std::fs::read(format!("{table_path}.sst")).unwrap();
+    data[data_size..].fill(0);
+    std::fs::write(format!("{table_path}.sst"), data).unwrap();
+
+    for i in 0..100 {
+        let value = tree.get(&format!("key_{i}")).unwrap();
+        assert_eq!(value, Some(format!("value_{i}")));
+    }
+
+    let stats = tree.stats();
+    assert_eq!(stats.table_cache_misses, misses);
+    assert!(stats.table_cache_hits >= 100);
+    assert!(SsTable::<String, History<String>>::load(table_path).is_err());
+}
+
+#[test]
+fn test_hot_keys_are_served_from_block_cache() {
+    let tree = lsm_three("test_hot_keys_are_served_from_block_cache");
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    for _ in 0..10 {
+        let value = tree.get(&"key_42".to_string()).unwrap();
+        assert_eq!(value, Some("value_42".to_string()));
+    }
+
+    let stats = tree.stats();
+    assert_eq!(stats.block_cache_misses, 1);
+    assert_eq!(stats.block_cache_hits, 9);
+}
+
+#[test]
+fn test_compaction_does_not_fill_block_cache_by_default() {
+    let tree = lsm_three("test_compaction_does_not_fill_block_cache_by_default");
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(tree.inner.block_cache.usage(), 0);
+}
+
+#[test]
+fn test_compaction_fills_block_cache_when_configured() {
+    let config = LsmTreeConfig {
+        memtable_size: 100,
+        compaction_fills_block_cache: true,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_compaction_fills_block_cache_when_configured", config);
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert!(tree.inner.block_cache.usage() > 0);
+}
+
+#[test]
+fn test_tree_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<LsmTree<String, String>>();
+
+    let name = "test_tree_can_be_shared_between_threads";
+    let tree = leveled_lsm_three(name);
+
+    std::thread::scope(|scope| {
+        for writer in 0..4 {
+            let tree = &tree;
+
+            scope.spawn(move || {
+                for i in 0..500 {
+                    tree.insert(format!("key_{writer}_{i:03}"), format!("value_{i}"))
+                        .unwrap();
+                }
+            });
+        }
+
+        for _ in 0..2 {
+            let tree = &tree;
+
+            scope.spawn(move || {
+                // Readers run alongside flushes and compactions, and see either no value yet or
+                // the written one.
+                for i in 0..500 {
+                    let value = tree.get(&format!("key_0_{i:03}")).unwrap();
+                    assert!(value.is_none_or(|value| value == format!("value_{i}")));
+                }
+            });
+        }
+    });
+
+    tree.flush().unwrap();
+
+    for writer in 0..4 {
+        for i in 0..500 {
+            let value = tree.get(&format!("key_{writer}_{i:03}")).unwrap();
+            assert_eq!(value, Some(format!("value_{i}")));
+        }
+    }
+
+    assert_eq!(tree.range(..).unwrap().count(), 2000);
+}
+
+#[test]
+fn test_memtables_are_flushed_in_background() {
+    let name = "test_memtables_are_flushed_in_background";
+    let tree = lsm_three(name);
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    // No explicit flush: full memtables reach level0 on their own.
+    let version = tree.inner.current(DEFAULT_FAMILY);
+    assert!(version.immutable.len() <= LsmTreeConfig::default().max_immutable_memtables);
+
+    let mut versions = tree.inner.versions.lock().unwrap();
+    while !versions[DEFAULT_FAMILY].immutable.is_empty() {
+        versions = tree.inner.version_changed.wait(versions).unwrap();
+    }
+    drop(versions);
+
+    assert_eq!(state(&tree).levels[0].len(), 9);
+}
+
+#[test]
+fn test_replaced_tables_are_kept_while_a_version_refers_to_them() {
+    let name = "test_replaced_tables_are_kept_while_a_version_refers_to_them";
+    let tree = lsm_three(name);
+
+    for i in 0..300 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    let level_0_files = || {
+        std::fs::read_dir(format!("target/{name}/level0"))
+            .unwrap()
+            .count()
+    };
+
+    let old_version = tree.inner.current(DEFAULT_FAMILY);
+    tree.compact().unwrap();
+
+    assert_eq!(level_0_files(), 3);
+    assert!(state(&tree).levels[0].is_empty());
+
+    // A reader of the old version still finds its tables.
+    let table = &old_version.levels[0][0];
+    let ss_table =
+        SsTable::<String, History<String>>::load(format!("target/{name}/level0/{}", table.meta.id));
+    assert!(ss_table.is_ok());
+
+    drop(old_version);
+
+    assert_eq!(level_0_files(), 0);
+}
+
+#[test]
+fn test_write_batch_applies_all_changes() {
+    let tree = lsm_three("test_write_batch_applies_all_changes");
+
+    for i in 0..20 {
+        tree.insert(format!("key_{i:02}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put("key_00".to_string(), "updated".to_string());
+    batch.delete("key_01".to_string());
+    batch.put("key_055".to_string(), "in_range".to_string());
+    batch.delete_range("key_05".to_string().."key_10".to_string());
+    batch.put("key_07".to_string(), "after_range".to_string());
+    tree.write(batch).unwrap();
+
+    let keys: Vec<_> = tree
+        .range(..)
+        .unwrap()
+        .map(|pair| pair.unwrap().0)
+        .collect();
+
+    let mut expected: Vec<_> = [0, 2, 3, 4, 7]
+        .into_iter()
+        .chain(10..20)
+        .map(|i| format!("key_{i:02}"))
+        .collect();
+    expected.sort();
+
+    assert_eq!(keys, expected);
+    assert_eq!(
+        tree.get(&"key_00".to_string()).unwrap(),
+        Some("updated".to_string())
+    );
+    assert_eq!(
+        tree.get(&"key_07".to_string()).unwrap(),
+        Some("after_range".to_string())
+    );
+}
+
+#[test]
+fn test_write_batch_is_never_split_between_memtables() {
+    let tree = lsm_three("test_write_batch_is_never_split_between_memtables");
+
+    for i in 0..90 {
+        tree.insert(format!("key_{i:03}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    let mut batch = WriteBatch::new();
+    for i in 90..110 {
+        batch.put(format!("key_{i:03}"), format!("value_{i}"));
+    }
+    tree.write(batch).unwrap();
+
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 20);
+
+    // A batch bigger than a memtable gets a memtable of its own.
+    let mut batch = WriteBatch::new();
+    for i in 110..400 {
+        batch.put(format!("key_{i:03}"), format!("value_{i}"));
+    }
+    tree.write(batch).unwrap();
+
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 290);
+    assert_eq!(tree.range(..).unwrap().count(), 400);
+}
+
+#[test]
+fn test_torn_write_batch_is_dropped_as_a_whole() {
+    let name = "test_torn_write_batch_is_dropped_as_a_whole";
+    let mut tree = lsm_three(name);
+
+    tree.insert("before".to_string(), "value".to_string())
+        .unwrap();
+
+    let mut batch = WriteBatch::new();
+    for i in 0..10 {
+        batch.put(format!("key_{i}"), format!("value_{i}"));
+    }
+    tree.write(batch).unwrap();
+
+    let wal_id = tree.inner.current(DEFAULT_FAMILY).memtable.wal_id;
+    tree.stop_background_work();
+    std::mem::forget(tree);
+
+    // The process dies in the middle of writing the batch.
+    let wal_path = format!("target/{name}/wal/{wal_id}");
+    let wal_size = std::fs::metadata(&wal_path).unwrap().len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&wal_path)
+        .unwrap()
+        .set_len(wal_size - 5)
+        .unwrap();
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+
+    assert_eq!(
+        tree.get(&"before".to_string()).unwrap(),
+        Some("value".to_string())
+    );
+    assert_eq!(tree.range(..).unwrap().count(), 1);
+}
+
+#[test]
+fn test_snapshot_does_not_see_later_writes() {
+    let tree = lsm_three("test_snapshot_does_not_see_later_writes");
+
+    tree.insert("key_1".to_string(), "old".to_string()).unwrap();
+    tree.insert("key_2".to_string(), "old".to_string()).unwrap();
+
+    let snapshot = tree.snapshot();
+
+    tree.insert("key_1".to_string(), "new".to_string()).unwrap();
+    tree.delete("key_2".to_string()).unwrap();
+    tree.insert("key_3".to_string(), "new".to_string()).unwrap();
+
+    assert_eq!(
+        snapshot.get(&"key_1".to_string()).unwrap(),
+        Some("old".to_string())
+    );
+    assert_eq!(
+        snapshot.get(&"key_2".to_string()).unwrap(),
+        Some("old".to_string())
+    );
+    assert_eq!(snapshot.get(&"key_3".to_string()).unwrap(), None);
+
+    let pairs: Vec<_> = snapshot.range(..).unwrap().map(Result::unwrap).collect();
+    assert_eq!(
+        pairs,
+        vec![
+            ("key_1".to_string(), "old".to_string()),
+            ("key_2".to_string(), "old".to_string()),
+        ]
+    );
+
+    assert_eq!(
+        tree.get(&"key_1".to_string()).unwrap(),
+        Some("new".to_string())
+    );
+    assert_eq!(tree.get(&"key_2".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_survives_flushes_and_compactions() {
+    let tree = leveled_lsm_three("test_snapshot_survives_flushes_and_compactions");
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), "old".to_string()).unwrap();
+    }
+
+    let snapshot = tree.snapshot();
+
+    for i in 0..500 {
+        if i % 2 == 0 {
+            tree.delete(format!("key_{i}")).unwrap();
+        } else {
+            tree.insert(format!("key_{i}"), "new".to_string()).unwrap();
+        }
+    }
+
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    for i in 0..500 {
+        assert_eq!(
+            snapshot.get(&format!("key_{i}")).unwrap(),
+            Some("old".to_string())
+        );
+    }
+    assert_eq!(snapshot.range(..).unwrap().count(), 500);
+    assert_eq!(tree.range(..).unwrap().count(), 250);
+}
+
+#[test]
+fn test_versions_are_dropped_once_no_snapshot_sees_them() {
+    let tree = leveled_lsm_three("test_versions_are_dropped_once_no_snapshot_sees_them");
+
+    tree.insert("key".to_string(), "old".to_string()).unwrap();
+
+    let snapshot = tree.snapshot();
+    let sequence = snapshot.sequence();
+    tree.insert("key".to_string(), "new".to_string()).unwrap();
+    tree.flush().unwrap();
+
+    let history = |tree: &LsmTree<String, String>| {
+        let version = tree.inner.current(DEFAULT_FAMILY);
+        let table = version.levels[0].last().unwrap();
+        let (_, history) = tree
+            .inner
+            .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 0, &table.meta)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        history
+    };
+
+    assert!(matches!(
+        history(&tree).visible(sequence).next(),
+        Some((_, Value::Data(value))) if value == "old"
+    ));
+
+    drop(snapshot);
+
+    // Both versions are in the memtable, but the older one is seen by no snapshot at the flush.
+    tree.insert("key".to_string(), "newer".to_string()).unwrap();
+    let sequence = tree.snapshot().sequence();
+    tree.insert("key".to_string(), "newest".to_string())
+        .unwrap();
+    tree.flush().unwrap();
+
+    assert!(history(&tree).visible(sequence).next().is_none());
+}
+
+#[test]
+fn test_synced_writes_survive_crash() {
+    let name = "test_synced_writes_survive_crash";
+    let config = LsmTreeConfig {
+        sync_writes: true,
+        ..Default::default()
+    };
+    let mut tree = configured_lsm_three(name, config);
+
+    for i in 0..10 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    tree.stop_background_work();
+    std::mem::forget(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+
+    assert!(state(&tree).config.sync_writes);
+    assert_eq!(tree.range(..).unwrap().count(), 10);
+}
+
+#[test]
+fn test_degenerate_config_is_rejected() {
+    for config in [
+        LsmTreeConfig {
+            level_0_size: 0,
+            ..Default::default()
+        },
+        LsmTreeConfig {
+            level_size_ratio: 0,
+            ..Default::default()
+        },
+        LsmTreeConfig {
+            ss_table_target_size: 0,
+            ..Default::default()
+        },
+        LsmTreeConfig {
+            ss_table_bloom_filter: BloomFilterPolicy::FalsePositiveRate(0.0),
+            ..Default::default()
+        },
+    ] {
+        let path = "target/test_degenerate_config_is_rejected";
+        let _ = std::fs::remove_dir_all(path);
+
+        assert!(LsmTree::<String, String>::with_config(path.to_string(), config).is_err());
+    }
+}
+
+#[test]
+fn test_smallest_target_size_gives_a_table_per_key() {
+    let config = LsmTreeConfig {
+        memtable_size: 10,
+        level_0_size: 1,
+        ss_table_target_size: 1,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_smallest_target_size_gives_a_table_per_key", config);
+
+    for i in 0..50 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(state(&tree).levels[1].len(), 50);
+    assert_eq!(tree.range(..).unwrap().count(), 50);
+}
+
+#[test]
+fn test_sizes_of_deep_levels_saturate() {
+    let config = LsmTreeConfig {
+        memtable_size: 100,
+        level_0_size: 2,
+        levels: 30,
+        level_size_ratio: 1000,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_sizes_of_deep_levels_saturate", config);
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(state(&tree).max_level_size(28), u64::MAX);
+    assert_eq!(tree.range(..).unwrap().count(), 500);
+}
+
+#[test]
+fn test_sequence_numbers_survive_reload() {
+    let path = "target/test_sequence_numbers_survive_reload";
+
+    let sequence = {
+        let tree = lsm_three("test_sequence_numbers_survive_reload");
+
+        for i in 0..250 {
+            tree.insert(format!("key_{i}"), format!("value_{i}"))
+                .unwrap();
+        }
+
+        tree.snapshot().sequence()
+    };
+
+    let tree = LsmTree::<String, String>::load(path.to_string()).unwrap();
+    assert_eq!(tree.snapshot().sequence(), sequence);
+
+    tree.insert("key_0".to_string(), "new".to_string()).unwrap();
+    assert_eq!(
+        tree.get(&"key_0".to_string()).unwrap(),
+        Some("new".to_string())
+    );
+}
+
+#[test]
+fn test_manifest_replays_flushes_and_compactions() {
+    let name = "test_manifest_replays_flushes_and_compactions";
+    let tree = leveled_lsm_three(name);
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let table_ids = |state: FamilyState<String>| -> Vec<Vec<u64>> {
+        state
+            .levels
+            .iter()
+            .map(|tables| tables.iter().map(|table| table.id).collect())
+            .collect()
+    };
+
+    let before = table_ids(state(&tree));
+    assert!(before[1..].iter().any(|tables| !tables.is_empty()));
+    drop(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    assert_eq!(table_ids(state(&tree)), before);
+
+    for i in 0..1000 {
+        let value = tree.get(&format!("key_{i}")).unwrap();
+        assert_eq!(value, Some(format!("value_{i}")));
+    }
+}
+
+#[test]
+fn test_load_switches_to_new_manifest() {
+    let name = "test_load_switches_to_new_manifest";
+    let path = format!("target/{name}");
+
+    let manifests = || -> Vec<String> {
+        std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("MANIFEST-"))
+            .collect()
+    };
+
+    drop(lsm_three(name));
+    let first = manifests();
+
+    drop(LsmTree::<String, String>::load(path.clone()).unwrap());
+    let second = manifests();
+
+    assert_eq!(first.len(), 1);
+    assert_eq!(second.len(), 1);
+    assert_ne!(first, second);
+
+    let current = std::fs::read_to_string(format!("{path}/CURRENT")).unwrap();
+    assert_eq!(current.trim(), second[0]);
+}
+
+#[test]
+fn test_pre_manifest_layout_is_reported() {
+    let path = "target/test_pre_manifest_layout_is_reported";
+    let _ = std::fs::remove_dir_all(path);
+
+    // The layout of the first trees: a `state` file next to the level directories.
+    std::fs::create_dir_all(format!("{path}/level0")).unwrap();
+    std::fs::write(format!("{path}/state"), [0; 16]).unwrap();
+
+    let error = LsmTree::<String, String>::load(path.to_string())
+        .err()
+        .unwrap();
+    assert!(
+        error
+            .to_string()
+            .contains("unsupported pre-manifest layout")
+    );
+}
+
+#[test]
+fn test_file_numbers_are_never_reused() {
+    let name = "test_file_numbers_are_never_reused";
+    let path = format!("target/{name}");
+
+    // Highest number of all files of the tree ever seen, including the ones removed since.
+    let mut highest = 0;
+    let mut tree = lsm_three(name);
+
+    for round in 0..3 {
+        let known: Vec<_> = state(&tree).levels.concat();
+
+        for i in 0..250 {
+            tree.insert(format!("key_{i}"), format!("value_{round}_{i}"))
+                .unwrap();
+        }
+        tree.flush().unwrap();
+        tree.compact().unwrap();
+
+        for table in state(&tree).levels.concat() {
+            if !known.iter().any(|known| known.id == table.id) {
+                assert!(table.id > highest);
+            }
+        }
+
+        let directories = (0..LsmTreeConfig::default().levels)
+            .map(|level| format!("{path}/level{level}"))
+            .chain([path.clone(), format!("{path}/wal")]);
+
+        for directory in directories {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+                let name = name.strip_prefix("MANIFEST-").unwrap_or(&name);
+
+                if let Ok(number) = name.split('.').next().unwrap().parse::<u64>() {
+                    highest = highest.max(number);
+                }
+            }
+        }
+
+        drop(tree);
+        tree = LsmTree::load(path.clone()).unwrap();
+    }
+}
+
+#[test]
+fn test_tree_recovers_from_crash_at_every_step() {
+    let steps = [
+        Step::FlushTableWritten,
+        Step::FlushEditLogged,
+        Step::FlushWalsRemoved,
+        Step::CompactionTableWritten,
+        Step::CompactionEditLogged,
+        Step::ManifestWritten,
+        Step::CurrentSwitched,
+    ];
+
+    for step in steps {
+        let name = format!("test_tree_recovers_from_crash_at_{step:?}");
+        let path = format!("target/{name}");
+        let mut tree = leveled_lsm_three(&name);
+        CRASH_AT.lock().unwrap().insert(path.clone(), step);
+
+        let (acknowledged, crashed) = crash(&mut tree);
+        std::mem::forget(tree);
+
+        // Steps of opening a tree are reached once it's loaded again.
+        let crashed = crashed || LsmTree::<String, String>::load(path.clone()).is_err();
+        assert!(crashed, "no crash at {step:?}");
+        CRASH_AT.lock().unwrap().remove(&path);
+
+        check_recovery(&name, &acknowledged);
+    }
+}
+
+#[test]
+fn test_tree_recovers_from_crash_before_table_is_renamed() {
+    let name = "test_tree_recovers_from_crash_before_table_is_renamed";
+    let path = format!("target/{name}");
+    let mut tree = leveled_lsm_three(name);
+    CRASH_AT
+        .lock()
+        .unwrap()
+        .insert(path.clone(), Step::FlushTableWritten);
+
+    let (acknowledged, crashed) = crash(&mut tree);
+    assert!(crashed);
+    CRASH_AT.lock().unwrap().remove(&path);
+
+    // SsTables are written to a temporary file renamed once it's complete, so a crash before the
+    // rename leaves the temporary file instead of the table the manifest doesn't know about.
+    let tables = state(&tree).levels[0].clone();
+    std::mem::forget(tree);
+    let mut renamed = 0;
+
+    for entry in std::fs::read_dir(format!("{path}/level0")).unwrap() {
+        let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+        let id: u64 = name.trim_end_matches(".sst").parse().unwrap();
+
+        if tables.iter().all(|table| table.id != id) {
+            let table_path = format!("{path}/level0/{name}");
+            std::fs::rename(&table_path, format!("{table_path}.tmp")).unwrap();
+            renamed += 1;
+        }
+    }
+    assert_eq!(renamed, 1);
+
+    check_recovery(name, &acknowledged);
+}
+
+#[test]
+fn test_delete_range_hides_covered_keys() {
+    let name = "test_delete_range_hides_covered_keys";
+    let tree = leveled_lsm_three(name);
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    tree.delete_range("key_0100".to_string().."key_0200".to_string())
+        .unwrap();
+    tree.insert("key_0150".to_string(), "again".to_string())
+        .unwrap();
+
+    let check = |tree: &LsmTree<String, String>| {
+        assert_eq!(
+            tree.get(&"key_0099".to_string()).unwrap(),
+            Some("value_99".to_string())
+        );
+        assert_eq!(tree.get(&"key_0100".to_string()).unwrap(), None);
+        assert_eq!(tree.get(&"key_0199".to_string()).unwrap(), None);
+        assert_eq!(
+            tree.get(&"key_0200".to_string()).unwrap(),
+            Some("value_200".to_string())
+        );
+        assert_eq!(
+            tree.get(&"key_0150".to_string()).unwrap(),
+            Some("again".to_string())
+        );
+
+        let keys: Vec<_> = tree
+            .range("key_0090".to_string().."key_0210".to_string())
+            .unwrap()
+            .map(|pair| pair.unwrap().0)
+            .collect();
+        assert_eq!(keys.len(), 10 + 1 + 10);
+        assert_eq!(keys[10], "key_0150");
+    };
+
+    check(&tree);
+
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+    check(&tree);
+
+    drop(tree);
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    check(&tree);
+}
+
+#[test]
+fn test_delete_range_writes_single_tombstone() {
+    let tree = lsm_three("test_delete_range_writes_single_tombstone");
+
+    tree.insert("key_1".to_string(), "value".to_string())
+        .unwrap();
+    tree.delete_range("key_0".to_string().."key_9".to_string())
+        .unwrap();
+
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 2);
+
+    tree.flush().unwrap();
+
+    let table = state(&tree).levels[0][0].clone();
+    let ss_table = tree
+        .inner
+        .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 0, &table)
+        .unwrap();
+
+    assert_eq!(ss_table.range_tombstones().len(), 1);
+    assert_eq!(ss_table.iter().unwrap().count(), 0);
+    assert_eq!(table.first_key, "key_0");
+    assert_eq!(table.last_key, "key_9");
+}
+
+#[test]
+fn test_compaction_drops_keys_covered_by_range_tombstone() {
+    let tree = lsm_three("test_compaction_drops_keys_covered_by_range_tombstone");
+
+    for i in 0..1000 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    tree.delete_range("key_0000".to_string().."key_0500".to_string())
+        .unwrap();
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let state = state(&tree);
+    assert!(state.levels[0].is_empty());
+
+    let mut stored = 0;
+    for (level, tables) in state.levels.iter().enumerate() {
+        for table in tables {
+            let ss_table = tree
+                .inner
+                .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, level, table)
+                .unwrap();
+
+            // Nothing is left below, so the tombstone isn't needed once the keys are gone.
+            assert!(ss_table.range_tombstones().is_empty());
+            stored += ss_table.iter().unwrap().count();
+        }
+    }
+
+    assert_eq!(stored, 500);
+    assert_eq!(tree.stats().purged_tombstones, 1);
+    assert_eq!(tree.range(..).unwrap().count(), 500);
+}
+
+#[test]
+fn test_snapshot_sees_keys_deleted_by_later_range_tombstone() {
+    let tree = leveled_lsm_three("test_snapshot_sees_keys_deleted_by_later_range_tombstone");
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+
+    let snapshot = tree.snapshot();
+    tree.delete_range("key_0000".to_string().."key_0250".to_string())
+        .unwrap();
+
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    assert_eq!(snapshot.range(..).unwrap().count(), 500);
+    assert_eq!(
+        snapshot.get(&"key_0010".to_string()).unwrap(),
+        Some("value_10".to_string())
+    );
+    assert_eq!(tree.range(..).unwrap().count(), 250);
+    assert_eq!(tree.get(&"key_0010".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_remove_does_not_read_ss_tables() {
+    let name = "test_remove_does_not_read_ss_tables";
+    let tree = lsm_three(name);
+
+    for i in 0..800 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    for table in &state(&tree).levels[0] {
+        corrupt_data_blocks(&format!("target/{name}/level0/{}", table.id));
+    }
+
+    assert!(tree.delete("key_5".to_string()).is_err());
+
+    tree.remove("key_5".to_string()).unwrap();
+    tree.remove("missing".to_string()).unwrap();
+
+    assert_eq!(tree.get(&"key_5".to_string()).unwrap(), None);
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 2);
+}
+
+#[test]
+fn test_remove_checked_reports_existing_keys() {
+    let tree = lsm_three("test_remove_checked_reports_existing_keys");
+
+    for i in 0..800 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    assert!(tree.remove_checked("key_5".to_string()).unwrap());
+    assert!(!tree.remove_checked("key_5".to_string()).unwrap());
+    assert_eq!(tree.get(&"key_5".to_string()).unwrap(), None);
+
+    // Bloom filters answer for a key no table holds.
+    let misses = tree.stats().block_cache_misses;
+    assert!(!tree.remove_checked("missing".to_string()).unwrap());
+    assert_eq!(tree.stats().block_cache_misses, misses);
+
+    // Only the tombstone of `key_5` is written.
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 1);
+}
+
+#[test]
+fn test_merge_applies_operands_to_value() {
+    let tree = lsm_three("test_merge_applies_operands_to_value").with_merge_operator(Append);
+
+    tree.insert("key_1".to_string(), "a".to_string()).unwrap();
+    tree.merge("key_1".to_string(), "b".to_string()).unwrap();
+    tree.merge("key_1".to_string(), "c".to_string()).unwrap();
+    tree.merge("key_2".to_string(), "x".to_string()).unwrap();
+
+    assert_eq!(
+        tree.get(&"key_1".to_string()).unwrap(),
+        Some("a,b,c".to_string())
+    );
+    assert_eq!(
+        tree.get(&"key_2".to_string()).unwrap(),
+        Some("x".to_string())
+    );
+
+    let pairs: Vec<_> = tree.range(..).unwrap().map(Result::unwrap).collect();
+    assert_eq!(
+        pairs,
+        vec![
+            ("key_1".to_string(), "a,b,c".to_string()),
+            ("key_2".to_string(), "x".to_string()),
+        ]
+    );
+
+    // Operands written after a delete start from scratch.
+    tree.remove("key_1".to_string()).unwrap();
+    tree.merge("key_1".to_string(), "d".to_string()).unwrap();
+    assert_eq!(
+        tree.get(&"key_1".to_string()).unwrap(),
+        Some("d".to_string())
+    );
+
+    tree.delete_range("key_0".to_string().."key_9".to_string())
+        .unwrap();
+    tree.merge("key_2".to_string(), "y".to_string()).unwrap();
+    assert_eq!(
+        tree.get(&"key_2".to_string()).unwrap(),
+        Some("y".to_string())
+    );
+}
+
+#[test]
+fn test_merge_requires_merge_operator() {
+    let path = "target/test_merge_requires_merge_operator".to_string();
+
+    {
+        let tree = lsm_three("test_merge_requires_merge_operator");
+        assert!(tree.merge("key".to_string(), "a".to_string()).is_err());
+
+        let tree = tree.with_merge_operator(Append);
+        tree.merge("key".to_string(), "a".to_string()).unwrap();
+    }
+
+    let tree = LsmTree::<String, String>::load(path).unwrap();
+    assert!(tree.get(&"key".to_string()).is_err());
+    assert!(tree.range(..).unwrap().next().unwrap().is_err());
+
+    let tree = tree.with_merge_operator(Append);
+    assert_eq!(tree.get(&"key".to_string()).unwrap(), Some("a".to_string()));
+}
+
+#[test]
+fn test_merge_operands_survive_flush_compaction_and_reload() {
+    let path = "target/test_merge_operands_survive_flush_compaction_and_reload".to_string();
+    let tree = leveled_lsm_three("test_merge_operands_survive_flush_compaction_and_reload")
+        .with_merge_operator(Append);
+
+    for round in 0..5 {
+        for i in 0..100 {
+            tree.merge(format!("key_{i:03}"), round.to_string())
+                .unwrap();
+        }
+        tree.flush().unwrap();
+    }
+
+    let expected = "0,1,2,3,4".to_string();
+    assert_eq!(
+        tree.get(&"key_042".to_string()).unwrap(),
+        Some(expected.clone())
+    );
+
+    tree.compact().unwrap();
+    assert_eq!(
+        tree.get(&"key_042".to_string()).unwrap(),
+        Some(expected.clone())
+    );
+
+    drop(tree);
+    let tree = LsmTree::load(path).unwrap().with_merge_operator(Append);
+
+    let pairs: Vec<_> = tree.range(..).unwrap().map(Result::unwrap).collect();
+    assert_eq!(pairs.len(), 100);
+    assert!(pairs.iter().all(|(_, value)| value == &expected));
+}
+
+#[test]
+fn test_compaction_folds_merge_operands() {
+    let tree = lsm_three("test_compaction_folds_merge_operands").with_merge_operator(Append);
+
+    tree.insert("key".to_string(), "a".to_string()).unwrap();
+    tree.flush().unwrap();
+    tree.merge("key".to_string(), "b".to_string()).unwrap();
+    tree.flush().unwrap();
+    tree.merge("key".to_string(), "c".to_string()).unwrap();
+    tree.flush().unwrap();
+
+    let snapshot = tree.snapshot();
+    tree.merge("key".to_string(), "d".to_string()).unwrap();
+    tree.flush().unwrap();
+
+    // The snapshot still needs the value without the last operand.
+    tree.compact().unwrap();
+    assert_eq!(
+        snapshot.get(&"key".to_string()).unwrap(),
+        Some("a,b,c".to_string())
+    );
+    drop(snapshot);
+
+    tree.merge("key".to_string(), "e".to_string()).unwrap();
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+
+    let state = state(&tree);
+    let table = &state.levels[1][0];
+    let (_, history) = tree
+        .inner
+        .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, 1, table)
+        .unwrap()
+        .iter()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+
+    let versions: Vec<_> = history.visible(u64::MAX).collect();
+    assert!(matches!(
+        versions.as_slice(),
+        [(_, Value::Data(value))] if value == "a,b,c,d,e"
+    ));
+}
+
+#[test]
+fn test_expired_entries_are_hidden() {
+    let clock = ManualClock::default();
+    let tree = lsm_three("test_expired_entries_are_hidden").with_clock(clock.clone());
+
+    for i in 0..10 {
+        tree.insert_with_ttl(
+            format!("key_{i}"),
+            format!("value_{i}"),
+            Duration::from_secs(i + 1),
+        )
+        .unwrap();
+    }
+    tree.insert("key_x".to_string(), "forever".to_string())
+        .unwrap();
+
+    assert_eq!(tree.range(..).unwrap().count(), 11);
+
+    clock.advance(Duration::from_secs(5));
+
+    assert_eq!(tree.get(&"key_4".to_string()).unwrap(), None);
+    assert_eq!(
+        tree.get(&"key_5".to_string()).unwrap(),
+        Some("value_5".to_string())
+    );
+    assert_eq!(tree.range(..).unwrap().count(), 6);
+
+    // An expired entry hides older versions of its key as a tombstone would.
+    tree.flush().unwrap();
+    tree.insert_with_ttl(
+        "key_x".to_string(),
+        "short".to_string(),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    clock.advance(Duration::from_secs(1));
+
+    assert_eq!(tree.get(&"key_x".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_expiry_survives_reload() {
+    let path = "target/test_expiry_survives_reload".to_string();
+    let clock = ManualClock::default();
+
+    {
+        let tree = lsm_three("test_expiry_survives_reload").with_clock(clock.clone());
+        tree.insert_with_ttl(
+            "key".to_string(),
+            "value".to_string(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+    }
+
+    let tree = LsmTree::<String, String>::load(path)
+        .unwrap()
+        .with_clock(clock.clone());
+    assert_eq!(
+        tree.get(&"key".to_string()).unwrap(),
+        Some("value".to_string())
+    );
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(tree.get(&"key".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_compaction_drops_expired_entries() {
+    let clock = ManualClock::default();
+    let tree = lsm_three("test_compaction_drops_expired_entries").with_clock(clock.clone());
+
+    for i in 0..1000 {
+        let key = format!("key_{i:04}");
+
+        if i % 2 == 0 {
+            tree.insert_with_ttl(key, format!("value_{i}"), Duration::from_secs(10))
+                .unwrap();
+        } else {
+            tree.insert(key, format!("value_{i}")).unwrap();
+        }
+    }
+    tree.flush().unwrap();
+
+    clock.advance(Duration::from_secs(10));
+    tree.compact().unwrap();
+
+    let state = state(&tree);
+    let stored: usize = state
+        .levels
+        .iter()
+        .enumerate()
+        .flat_map(|(level, tables)| tables.iter().map(move |table| (level, table)))
+        .map(|(level, table)| {
+            let ss_table = tree
+                .inner
+                .load_ss_table(&tree.inner.current(DEFAULT_FAMILY).config, level, table)
+                .unwrap();
+            ss_table.iter().unwrap().count()
+        })
+        .sum();
+
+    assert_eq!(stored, 500);
+    assert_eq!(tree.range(..).unwrap().count(), 500);
+}
+
+#[test]
+fn test_column_families_are_separate_keyspaces() {
+    let name = "test_column_families_are_separate_keyspaces";
+    let tree = lsm_three(name);
+
+    let config = LsmTreeConfig {
+        memtable_size: 20,
+        ss_table_block_size: 4,
+        ..Default::default()
+    };
+    let users = tree.create_column_family("users", config).unwrap();
+    assert!(
+        tree.create_column_family("users", LsmTreeConfig::default())
+            .is_err()
+    );
+
+    for i in 0..100 {
+        tree.insert(format!("key_{i}"), format!("default_{i}"))
+            .unwrap();
+        users
+            .insert(format!("key_{i}"), format!("user_{i}"))
+            .unwrap();
+    }
+    users.remove("key_0".to_string()).unwrap();
+
+    assert_eq!(
+        tree.get(&"key_0".to_string()).unwrap(),
+        Some("default_0".to_string())
+    );
+    assert_eq!(users.get(&"key_0".to_string()).unwrap(), None);
+    assert_eq!(users.range(..).unwrap().count(), 99);
+
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+    drop(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    assert_eq!(tree.column_families(), vec!["default", "users"]);
+    assert!(tree.column_family("orders").is_err());
+
+    let users = tree.column_family("users").unwrap();
+    assert_eq!(users.name(), "users");
+    assert_eq!(
+        users.get(&"key_42".to_string()).unwrap(),
+        Some("user_42".to_string())
+    );
+    assert_eq!(
+        tree.get(&"key_42".to_string()).unwrap(),
+        Some("default_42".to_string())
+    );
+
+    let families = tree.inner.state.lock().unwrap().families.clone();
+    assert_eq!(families[1].config.memtable_size, 20);
+    assert!(families[1].levels.iter().any(|tables| !tables.is_empty()));
+}
+
+#[test]
+fn test_write_batch_spans_column_families() {
+    let tree = lsm_three("test_write_batch_spans_column_families");
+    let index = tree
+        .create_column_family("index", LsmTreeConfig::default())
+        .unwrap();
+
+    index.insert("stale".to_string(), "1".to_string()).unwrap();
+    let before = tree.snapshot();
+
+    let mut batch = WriteBatch::new();
+    batch.put("user_1".to_string(), "Alice".to_string());
+    batch.put_cf(&index, "Alice".to_string(), "user_1".to_string());
+    batch.delete_cf(&index, "stale".to_string());
+    tree.write(batch).unwrap();
+
+    let after = tree.snapshot();
+
+    assert_eq!(before.get(&"user_1".to_string()).unwrap(), None);
+    assert_eq!(before.get_cf(&index, &"Alice".to_string()).unwrap(), None);
+    assert_eq!(
+        before.get_cf(&index, &"stale".to_string()).unwrap(),
+        Some("1".to_string())
+    );
+
+    assert_eq!(
+        after.get(&"user_1".to_string()).unwrap(),
+        Some("Alice".to_string())
+    );
+    assert_eq!(
+        after.get_cf(&index, &"Alice".to_string()).unwrap(),
+        Some("user_1".to_string())
+    );
+    assert_eq!(after.range_cf(&index, ..).unwrap().count(), 1);
+}
+
+#[test]
+fn test_write_batch_filling_several_families_switches_wal_once() {
+    let tree = lsm_three("test_write_batch_filling_several_families_switches_wal_once");
+    let index = tree
+        .create_column_family(
+            "index",
+            LsmTreeConfig {
+                memtable_size: 10,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    for i in 0..100 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    for i in 0..10 {
+        index
+            .insert(format!("value_{i}"), format!("key_{i}"))
+            .unwrap();
+    }
+
+    let mut batch = WriteBatch::new();
+    batch.put("key_100".to_string(), "value_100".to_string());
+    batch.put_cf(&index, "value_100".to_string(), "key_100".to_string());
+    tree.write(batch).unwrap();
+
+    let wal_id = tree.inner.writer.lock().unwrap().wal_id;
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.wal_id, wal_id);
+    assert_eq!(tree.inner.current(index.id()).memtable.wal_id, wal_id);
+    assert_eq!(tree.inner.current(DEFAULT_FAMILY).memtable.len(), 1);
+    assert_eq!(tree.inner.current(index.id()).memtable.len(), 1);
+}
+
+#[test]
+fn test_idle_family_does_not_keep_old_wals() {
+    let name = "test_idle_family_does_not_keep_old_wals";
+    let config = LsmTreeConfig {
+        memtable_size: 10,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three(name, config);
+    tree.create_column_family("idle", LsmTreeConfig::default())
+        .unwrap();
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+
+    let wal_files = std::fs::read_dir(format!("target/{name}/wal"))
+        .unwrap()
+        .count();
+    assert_eq!(wal_files, 1);
+    drop(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    assert_eq!(tree.range(..).unwrap().count(), 500);
+}
+
+#[test]
+fn test_shared_wal_is_kept_until_all_families_are_flushed() {
+    let name = "test_shared_wal_is_kept_until_all_families_are_flushed";
+    let mut tree = lsm_three(name);
+    let users = tree
+        .create_column_family("users", LsmTreeConfig::default())
+        .unwrap();
+
+    let wal_files = || {
+        std::fs::read_dir(format!("target/{name}/wal"))
+            .unwrap()
+            .count()
+    };
+
+    for i in 0..50 {
+        tree.insert(format!("key_{i}"), format!("default_{i}"))
+            .unwrap();
+        users
+            .insert(format!("key_{i}"), format!("user_{i}"))
+            .unwrap();
+    }
+
+    // The first WAL still holds writes of the default family.
+    users.flush().unwrap();
+    assert_eq!(wal_files(), 2);
+
+    for i in 50..100 {
+        users
+            .insert(format!("key_{i}"), format!("user_{i}"))
+            .unwrap();
+    }
+
+    // Killed without flushing the active memtables.
+    tree.stop_background_work();
+    std::mem::forget(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+    let users = tree.column_family("users").unwrap();
+
+    assert_eq!(tree.range(..).unwrap().count(), 50);
+    assert_eq!(users.range(..).unwrap().count(), 100);
+
+    tree.flush().unwrap();
+    assert_eq!(wal_files(), 1);
+}
+
+#[test]
+fn test_every_memtable_kind_serves_the_same_data() {
+    for kind in [
+        MemtableKind::BTree,
+        MemtableKind::SkipList,
+        MemtableKind::Arena,
+    ] {
+        let name = format!("test_every_memtable_kind_serves_the_same_data_{kind:?}");
+        let config = LsmTreeConfig {
+            memtable_size: 100,
+            memtable: kind,
+            ..Default::default()
+        };
+        let tree = configured_lsm_three(&name, config);
+
+        for i in 0..250 {
+            tree.insert(format!("key_{i:03}"), format!("value_{i}"))
+                .unwrap();
+        }
+        tree.remove("key_007".to_string()).unwrap();
+        tree.delete_range("key_100".to_string().."key_200".to_string())
+            .unwrap();
+
+        let check = |tree: &LsmTree<String, String>| {
+            assert_eq!(tree.get(&"key_007".to_string()).unwrap(), None);
+            assert_eq!(tree.get(&"key_150".to_string()).unwrap(), None);
+            assert_eq!(
+                tree.get(&"key_249".to_string()).unwrap(),
+                Some("value_249".to_string())
+            );
+            assert_eq!(tree.range(..).unwrap().count(), 149, "{kind:?}");
+        };
+
+        check(&tree);
+        drop(tree);
+
+        let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+        assert_eq!(state(&tree).config.memtable, kind);
+        check(&tree);
+    }
+}
+
+#[test]
+fn test_memtable_is_flushed_once_it_takes_max_bytes() {
+    let config = LsmTreeConfig {
+        memtable_size: 1_000_000,
+        memtable_max_bytes: 4096,
+        level_0_size: 100,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three("test_memtable_is_flushed_once_it_takes_max_bytes", config);
+
+    // Values vary in size a lot, but every table gets about the same amount of data.
+    for i in 0..200 {
+        let value = "x".repeat(if i % 10 == 0 { 1000 } else { 10 });
+        tree.insert(format!("key_{i:03}"), value).unwrap();
+
+        assert!(tree.inner.current(DEFAULT_FAMILY).memtable.size() <= 4096);
+    }
+    tree.flush().unwrap();
+
+    let tables = &state(&tree).levels[0];
+    assert!(tables.len() >= 5);
+    assert!(tables.iter().all(|table| table.size <= 2 * 4096));
+    assert_eq!(tree.range(..).unwrap().count(), 200);
+}
+
+#[test]
+fn test_tables_with_partitioned_indexes_survive_compaction() {
+    let name = "test_tables_with_partitioned_indexes_survive_compaction";
+    let config = LsmTreeConfig {
+        memtable_size: 500,
+        ss_table_block_size: 4,
+        ss_table_index_partition_bytes: Some(128),
+        pin_index_partitions: true,
+        level_0_size: 2,
+        ..Default::default()
+    };
+    let tree = configured_lsm_three(name, config);
+
+    for i in 0..2000 {
+        tree.insert(format!("key_{i:04}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    tree.compact().unwrap();
+    drop(tree);
+
+    let tree = LsmTree::<String, String>::load(format!("target/{name}")).unwrap();
+
+    for i in (0..2000).step_by(7) {
+        assert_eq!(
+            tree.get(&format!("key_{i:04}")).unwrap(),
+            Some(format!("value_{i}"))
+        );
+    }
+    assert_eq!(
+        tree.range("key_1000".to_string().."key_1100".to_string())
+            .unwrap()
+            .count(),
+        100
+    );
+}
+
+#[test]
+fn test_index_partitions_are_pinned_per_column_family() {
+    let config = LsmTreeConfig {
+        ss_table_block_size: 4,
+        ss_table_index_partition_bytes: Some(128),
+        ..Default::default()
+    };
+    let tree = configured_lsm_three(
+        "test_index_partitions_are_pinned_per_column_family",
+        config.clone(),
+    );
+    let pinned = tree
+        .create_column_family(
+            "pinned",
+            LsmTreeConfig {
+                pin_index_partitions: true,
+                ..config
+            },
+        )
+        .unwrap();
+
+    for i in 0..500 {
+        tree.insert(format!("key_{i:03}"), format!("value_{i}"))
+            .unwrap();
+        pinned
+            .insert(format!("key_{i:03}"), format!("value_{i}"))
+            .unwrap();
+    }
+    tree.flush().unwrap();
+    pinned.flush().unwrap();
+
+    // Once both blocks are cached, a lookup hits the cache for the index partition only when
+    // it's not pinned.
+    let hits_of_second_lookup = |get: &dyn Fn() -> Option<String>| {
+        get();
+        let hits = tree.inner.block_cache.hits();
+        assert_eq!(get(), Some("value_250".to_string()));
+        tree.inner.block_cache.hits() - hits
+    };
+    let key = "key_250".to_string();
+
+    assert_eq!(hits_of_second_lookup(&|| tree.get(&key).unwrap()), 2);
+    assert_eq!(hits_of_second_lookup(&|| pinned.get(&key).unwrap()), 1);
+}
+
+/// Layout of the default column family.
+fn state(tree: &LsmTree<String, String>) -> FamilyState<String> {
+    tree.inner.state.lock().unwrap().families[DEFAULT_FAMILY].clone()
+}
+
+/// Writes to `tree`, then flushes and compacts it, until a simulated crash stops it. Returns the
+/// acknowledged writes and whether the tree crashed, leaving it stopped as a killed process would.
+fn crash(tree: &mut LsmTree<String, String>) -> (Vec<(String, String)>, bool) {
+    let mut acknowledged = Vec::new();
+
+    for i in 0..1000 {
+        let (key, value) = (format!("key_{i}"), format!("value_{i}"));
+
+        if tree.insert(key.clone(), value.clone()).is_ok() {
+            acknowledged.push((key, value));
+        }
+    }
+
+    let crashed = tree.flush().is_err() || tree.compact().is_err();
+    tree.stop_background_work();
+
+    (acknowledged, crashed)
+}
+
+/// Loads the tree of `test_name` after a crash and checks it holds the `acknowledged` writes and
+/// only the tables of its manifest.
+fn check_recovery(test_name: &str, acknowledged: &[(String, String)]) {
+    let tree = LsmTree::<String, String>::load(format!("target/{test_name}")).unwrap();
+
+    for (key, value) in acknowledged {
+        assert_eq!(tree.get(key).unwrap().as_ref(), Some(value), "{test_name}");
+    }
+
+    // Background work of the reloaded tree is done once the memtables are flushed and no
+    // compaction holds the lock, so only the tables of the manifest may be left on disk.
+    tree.flush().unwrap();
+    let compaction = tree.inner.compaction.lock().unwrap();
+
+    for (level, tables) in state(&tree).levels.iter().enumerate() {
+        let mut files: Vec<_> = std::fs::read_dir(format!("target/{test_name}/level{level}"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+
+        let mut expected: Vec<_> = tables
+            .iter()
+            .map(|table| format!("{}.sst", table.id))
+            .collect();
+        expected.sort();
+
+        assert_eq!(files, expected, "{test_name}");
+    }
+
+    drop(compaction);
+
+    tree.compact().unwrap();
+    assert_eq!(tree.range(..).unwrap().count(), acknowledged.len());
+}
+
+/// Overwrites all data blocks of a table, leaving its bloom filter and index intact, so any read
+/// of the table's data fails.
+fn corrupt_data_blocks(table_path: &str) {
+    let data_size = SsTable::<String, History<String>>::load(table_path.to_string())
+        .unwrap()
+        .properties()
+        .data_size as usize;
+
+    let path = format!("{table_path}.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[..data_size].fill(0);
+    std::fs::write(path, data).unwrap();
+}
+
+fn leveled_lsm_three(test_name: &str) -> LsmTree<String, String> {
+    let config = LsmTreeConfig {
+        memtable_size: 100,
+        level_0_size: 2,
+        ss_table_block_size: 10,
+        levels: 4,
+        level_1_max_size: 4 * 1024,
+        level_size_ratio: 2,
+        ss_table_target_size: 1024,
+        ..Default::default()
+    };
+
+    configured_lsm_three(test_name, config)
+}
+
+fn lsm_three(test_name: &str) -> LsmTree<String, String> {
+    let path = format!("target/{test_name}");
+    let _ = std::fs::remove_dir_all(&path);
+
+    LsmTree::new(path, 100, 10, 10).unwrap()
+}
+
+fn configured_lsm_three(test_name: &str, config: LsmTreeConfig) -> LsmTree<String, String> {
+    let path = format!("target/{test_name}");
+    let _ = std::fs::remove_dir_all(&path);
+
+    LsmTree::with_config(path, config).unwrap()
+}
+
+/// Joins the operands to the value with commas.
+struct Append;
+
+impl MergeOperator<String, String> for Append {
+    fn merge(&self, _key: &String, existing: Option<&String>, operands: &[String]) -> String {
+        existing
+            .into_iter()
+            .chain(operands)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Clock which moves only when a test advances it.
+#[derive(Clone, Default)]
+struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
 }
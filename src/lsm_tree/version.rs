@@ -0,0 +1,194 @@
+use crate::{
+    lsm_tree::{
+        LsmTreeConfig, TableCache, TableMeta, Value,
+        history::History,
+        memtable::{Memtable, MemtableKind, encoded_size},
+    },
+    sstable::{RangeTombstone, SsTable},
+};
+use std::{
+    collections::BTreeMap,
+    hash::Hash,
+    ops::Bound,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+/// Snapshot of everything a reader of a column family of an
+/// [`LsmTree`](crate::lsm_tree::LsmTree) looks at: the memtables and the SsTables of all levels.
+///
+/// A version never changes once it's installed. Writers and background threads install new
+/// versions, while readers keep using the one they started with.
+pub(super) struct Version<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
+    V: Clone + bincode::Encode + bincode::Decode<()>,
+{
+    /// Tuning of the column family, which never changes.
+    pub(super) config: Arc<LsmTreeConfig>,
+    /// Memtable receiving new writes.
+    pub(super) memtable: Arc<WriteBuffer<K, V>>,
+    /// Full memtables waiting to be flushed, from the oldest to the newest.
+    pub(super) immutable: Vec<Arc<WriteBuffer<K, V>>>,
+    pub(super) levels: Vec<Vec<Arc<TableFile<K, V>>>>,
+}
+
+/// Memtable of a column family with the range tombstones written into it, backed by WAL files.
+pub(super) struct WriteBuffer<K, V>
+where
+    V: Clone,
+{
+    /// Id of the WAL file holding the first writes of the memtable. The WAL is shared by all
+    /// column families, so a memtable outlives it when another family starts a new one, and the
+    /// rest of its writes go to the later WALs.
+    pub(super) wal_id: u64,
+    memtable: Box<dyn Memtable<K, V>>,
+    range_tombstones: RwLock<Vec<RangeTombstone<K>>>,
+    /// Number of versions of all keys and range tombstones in the memtable.
+    versions: AtomicUsize,
+    /// Approximate size in bytes of the versions and range tombstones, as they are encoded.
+    size: AtomicUsize,
+}
+
+impl<K, V> WriteBuffer<K, V>
+where
+    K: Clone + Ord + bincode::Encode + Send + Sync + 'static,
+    V: Clone + bincode::Encode + Send + Sync + 'static,
+{
+    pub(super) fn new(wal_id: u64, kind: MemtableKind) -> Self {
+        Self {
+            wal_id,
+            memtable: kind.create(),
+            range_tombstones: RwLock::new(Vec::new()),
+            versions: AtomicUsize::new(0),
+            size: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns all versions of `key` the memtable holds.
+    pub(super) fn get(&self, key: &K) -> Option<History<V>> {
+        self.memtable.get(key)
+    }
+
+    /// Inserts all pairs and deleted ranges of a write. Readers don't see them before the tree
+    /// publishes `sequence`, so they see either none or all of them.
+    pub(super) fn insert(
+        &self,
+        sequence: u64,
+        pairs: Vec<(K, Value<V>)>,
+        deleted_ranges: Vec<(K, K)>,
+    ) {
+        let mut range_tombstones = self.range_tombstones.write().unwrap();
+        self.versions
+            .fetch_add(pairs.len() + deleted_ranges.len(), Ordering::SeqCst);
+
+        for (start, end) in deleted_ranges {
+            let tombstone = RangeTombstone {
+                start,
+                end,
+                sequence,
+            };
+
+            self.size
+                .fetch_add(encoded_size(&tombstone), Ordering::SeqCst);
+            range_tombstones.push(tombstone);
+        }
+
+        for (key, value) in pairs {
+            self.size
+                .fetch_add(encoded_size(&(&key, sequence, &value)), Ordering::SeqCst);
+            self.memtable.insert(key, sequence, value);
+        }
+    }
+
+    /// Number of versions of all keys in the memtable.
+    pub(super) fn len(&self) -> usize {
+        self.versions.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate size in bytes the versions and range tombstones of the memtable take once they
+    /// are written into an SsTable.
+    pub(super) fn size(&self) -> usize {
+        self.size.load(Ordering::SeqCst)
+    }
+
+    /// Copies the histories of the keys in `bounds`.
+    pub(super) fn range(&self, bounds: (Bound<K>, Bound<K>)) -> Vec<(K, History<V>)> {
+        self.memtable.range(bounds)
+    }
+
+    pub(super) fn to_map(&self) -> BTreeMap<K, History<V>> {
+        self.range((Bound::Unbounded, Bound::Unbounded))
+            .into_iter()
+            .collect()
+    }
+
+    pub(super) fn range_tombstones(&self) -> Vec<RangeTombstone<K>> {
+        self.range_tombstones.read().unwrap().clone()
+    }
+}
+
+/// SsTable referenced by versions of the tree.
+///
+/// Once compaction replaces a table, the table is marked obsolete, and its files are removed as
+/// soon as the last version referring to it is gone, so readers of older versions never lose a
+/// table in the middle of a lookup.
+pub(super) struct TableFile<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
+    V: Clone + bincode::Encode + bincode::Decode<()>,
+{
+    pub(super) level: usize,
+    pub(super) meta: TableMeta<K>,
+    path: String,
+    obsolete: AtomicBool,
+    table_cache: Arc<TableCache<K, V>>,
+}
+
+impl<K, V> TableFile<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
+    V: Clone + bincode::Encode + bincode::Decode<()>,
+{
+    pub(super) fn new(
+        level: usize,
+        meta: TableMeta<K>,
+        path: String,
+        table_cache: Arc<TableCache<K, V>>,
+    ) -> Self {
+        Self {
+            level,
+            meta,
+            path,
+            obsolete: AtomicBool::new(false),
+            table_cache,
+        }
+    }
+
+    pub(super) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<K, V> Drop for TableFile<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()>,
+    V: Clone + bincode::Encode + bincode::Decode<()>,
+{
+    fn drop(&mut self) {
+        if !self.obsolete.load(Ordering::SeqCst) {
+            return;
+        }
+
+        self.table_cache.lock().unwrap().remove(&self.meta.id);
+
+        // Nobody refers to the table anymore, so a file left behind is just wasted space.
+        let _ = SsTable::<K, History<V>>::remove(&self.path);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::lsm_tree::{ColumnFamily, DEFAULT_FAMILY};
+use std::{hash::Hash, ops::Range};
+
+/// Set of changes which [`LsmTree::write`](crate::lsm_tree::LsmTree::write) applies all at once.
+///
+/// Changes are applied in the order they were added to the batch, so e.g. a key put into the batch
+/// and then covered by a deleted range ends up deleted. Plain changes go to the default column
+/// family, while the `_cf` variants may change any other family within the same batch.
+pub struct WriteBatch<K, V> {
+    /// Changes with the ids of their column families.
+    pub(super) operations: Vec<(usize, Operation<K, V>)>,
+}
+
+pub(super) enum Operation<K, V> {
+    Put(K, V),
+    Delete(K),
+    DeleteRange(Range<K>),
+}
+
+impl<K, V> Default for WriteBatch<K, V> {
+    fn default() -> Self {
+        Self {
+            operations: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> WriteBatch<K, V>
+where
+    K: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        self.operations
+            .push((DEFAULT_FAMILY, Operation::Put(key, value)));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.operations
+            .push((DEFAULT_FAMILY, Operation::Delete(key)));
+    }
+
+    /// Deletes all keys in `range`, both the ones already in the tree and the ones put earlier
+    /// into the batch, with a single range tombstone.
+    pub fn delete_range(&mut self, range: Range<K>) {
+        self.operations
+            .push((DEFAULT_FAMILY, Operation::DeleteRange(range)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl<K, V> WriteBatch<K, V>
+where
+    K: Hash + Clone + Ord + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+    V: Clone + bincode::Encode + bincode::Decode<()> + Send + Sync + 'static,
+{
+    pub fn put_cf(&mut self, family: &ColumnFamily<K, V>, key: K, value: V) {
+        self.operations
+            .push((family.id(), Operation::Put(key, value)));
+    }
+
+    pub fn delete_cf(&mut self, family: &ColumnFamily<K, V>, key: K) {
+        self.operations.push((family.id(), Operation::Delete(key)));
+    }
+
+    pub fn delete_range_cf(&mut self, family: &ColumnFamily<K, V>, range: Range<K>) {
+        self.operations
+            .push((family.id(), Operation::DeleteRange(range)));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::sstable::block_cache::Block;
+use std::{borrow::Cow, error::Error, marker::PhantomData, ops::Range};
+
+/// Size of an offset in the restart array of a block, and of the number of offsets closing it.
+const RESTART_SIZE: usize = 4;
+
+/// How pairs are laid out in the data blocks of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BlockFormat {
+    /// Pairs encoded one after another, as tables of format version 1 and of the legacy layout
+    /// store them.
+    Plain,
+    /// Every pair stores only the part of its encoded key which differs from the key before it.
+    /// Every few pairs a restart point stores the whole key, and the block ends with the offsets
+    /// of the restart points and their number, so lookups binary search the restart points.
+    /// Tables of format versions 2 to 4 store them, though string keys of different lengths
+    /// share nothing there, as their encodings start with their lengths.
+    EncodedPrefixCompressed,
+    /// Like [`BlockFormat::EncodedPrefixCompressed`], but keys are stored with the varint their
+    /// encoding starts with moved behind the rest, so string keys of different lengths share
+    /// the prefixes of their contents.
+    PrefixCompressed,
+}
+
+/// Writes pairs into a data block of the [`BlockFormat::PrefixCompressed`] format.
+///
+/// A pair is stored as the length of the prefix its key shares with the key before it, the
+/// length of the rest of the key and the length of the value, followed by the rest of the key
+/// and the value. Keys are compared as [`length_last`] lays them out.
+pub(super) struct BlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    /// Number of pairs added since the last restart point, wrapping at `restart_interval`.
+    since_restart: usize,
+    /// Stored key of the last pair.
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    /// Starts a block with a restart point every `restart_interval` pairs.
+    pub(super) fn new(restart_interval: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            restarts: Vec::new(),
+            restart_interval: restart_interval.max(1),
+            since_restart: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Adds a pair whose key goes after the keys of all pairs added before.
+    pub(super) fn add<K, V>(&mut self, key: &K, value: &V) -> Result<(), Box<dyn Error>>
+    where
+        K: bincode::Encode,
+        V: bincode::Encode,
+    {
+        let key = length_last(bincode::encode_to_vec(key, bincode::config::standard())?);
+        let value = bincode::encode_to_vec(value, bincode::config::standard())?;
+
+        let shared = if self.since_restart == 0 {
+            self.restarts.push(u32::try_from(self.buffer.len())?);
+            0
+        } else {
+            self.last_key
+                .iter()
+                .zip(&key)
+                .take_while(|(last, next)| last == next)
+                .count()
+        };
+
+        bincode::encode_into_std_write(
+            (shared, key.len() - shared, value.len()),
+            &mut self.buffer,
+            bincode::config::standard(),
+        )?;
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(&value);
+
+        self.last_key = key;
+        self.since_restart = (self.since_restart + 1) % self.restart_interval;
+
+        Ok(())
+    }
+
+    /// Size of the block if it was finished now.
+    pub(super) fn size(&self) -> usize {
+        self.buffer.len() + (self.restarts.len() + 1) * RESTART_SIZE
+    }
+
+    /// Returns the finished block and starts an empty one.
+    pub(super) fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buffer);
+
+        for restart in &self.restarts {
+            block.extend_from_slice(&restart.to_le_bytes());
+        }
+
+        block.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+
+        self.restarts.clear();
+        self.since_restart = 0;
+        self.last_key.clear();
+
+        block
+    }
+}
+
+/// Key of a pair with the position of its encoded value in the block.
+type Entry<K> = (K, Range<usize>);
+
+/// Cursor over the pairs of a data block, in ascending order of keys.
+pub(super) struct BlockIter<K, V> {
+    block: Block,
+    format: BlockFormat,
+    /// End of the pairs, where the restart array starts.
+    end: usize,
+    restarts: usize,
+    position: usize,
+    /// Stored key of the pair before `position`.
+    key: Vec<u8>,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> BlockIter<K, V>
+where
+    K: Ord + bincode::Decode<()>,
+    V: bincode::Decode<()>,
+{
+    pub(super) fn new(block: Block, format: BlockFormat) -> Result<Self, Box<dyn Error>> {
+        let (end, restarts) = match format {
+            BlockFormat::Plain => (block.len(), 0),
+            BlockFormat::EncodedPrefixCompressed | BlockFormat::PrefixCompressed => {
+                let count_offset = block
+                    .len()
+                    .checked_sub(RESTART_SIZE)
+                    .ok_or("data block is too short")?;
+                let restarts = read_u32(&block, count_offset) as usize;
+
+                let end = restarts
+                    .checked_mul(RESTART_SIZE)
+                    .and_then(|size| count_offset.checked_sub(size))
+                    .ok_or("restart array of a data block is cut short")?;
+
+                (end, restarts)
+            }
+        };
+
+        Ok(Self {
+            block,
+            format,
+            end,
+            restarts,
+            position: 0,
+            key: Vec::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Moves the cursor to the restart point closest before the first pair whose key is not
+    /// smaller than `key`, so only a few pairs are read until it. Blocks without restart points
+    /// are read from the start.
+    pub(super) fn seek(&mut self, key: &K) -> Result<(), Box<dyn Error>> {
+        if self.format == BlockFormat::Plain {
+            self.position = 0;
+            return Ok(());
+        }
+
+        if self.restarts == 0 {
+            self.position = self.end;
+            return Ok(());
+        }
+
+        // The key of the restart point at `low` is smaller than `key`, unless it's the first one,
+        // and the key at `high` is not.
+        let (mut low, mut high) = (0, self.restarts);
+
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+
+            if self.restart_key(middle)? < *key {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        self.position = self.restart(low)?;
+        self.key.clear();
+
+        Ok(())
+    }
+
+    /// Returns the value of `key`, if the block holds it.
+    pub(super) fn get(&mut self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
+        self.seek(key)?;
+
+        while let Some((candidate, value)) = self.next_entry()? {
+            if candidate == *key {
+                return Ok(Some(self.decode_value(value)?));
+            }
+
+            if candidate > *key {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub(super) fn next_pair(&mut self) -> Result<Option<(K, V)>, Box<dyn Error>> {
+        let Some((key, value)) = self.next_entry()? else {
+            return Ok(None);
+        };
+
+        Ok(Some((key, self.decode_value(value)?)))
+    }
+
+    /// Reads the key of the next pair and returns it with the position of the encoded value.
+    pub(super) fn next_entry(&mut self) -> Result<Option<Entry<K>>, Box<dyn Error>> {
+        if self.position >= self.end {
+            return Ok(None);
+        }
+
+        let entry = &self.block[self.position..self.end];
+
+        match self.format {
+            BlockFormat::Plain => {
+                let (key, key_size) =
+                    bincode::decode_from_slice(entry, bincode::config::standard())?;
+
+                // Plain pairs don't store the size of the value, so it takes decoding to skip it.
+                let (_, value_size): (V, _) =
+                    bincode::decode_from_slice(&entry[key_size..], bincode::config::standard())?;
+
+                let value = self.position + key_size..self.position + key_size + value_size;
+                self.position = value.end;
+
+                Ok(Some((key, value)))
+            }
+            BlockFormat::EncodedPrefixCompressed | BlockFormat::PrefixCompressed => {
+                let ((shared, unshared, value_size), header_size): ((usize, usize, usize), _) =
+                    bincode::decode_from_slice(entry, bincode::config::standard())?;
+
+                let value_start = header_size
+                    .checked_add(unshared)
+                    .filter(|_| shared <= self.key.len())
+                    .ok_or("corrupted pair in a data block")?;
+                let value_end = value_start
+                    .checked_add(value_size)
+                    .filter(|&value_end| value_end <= entry.len())
+                    .ok_or("corrupted pair in a data block")?;
+
+                self.key.truncate(shared);
+                self.key.extend_from_slice(&entry[header_size..value_start]);
+
+                let key = self.decode_key(&self.key)?;
+
+                let value = self.position + value_start..self.position + value_end;
+                self.position = value.end;
+
+                Ok(Some((key, value)))
+            }
+        }
+    }
+
+    fn decode_value(&self, value: Range<usize>) -> Result<V, Box<dyn Error>> {
+        let (value, _) =
+            bincode::decode_from_slice(&self.block[value], bincode::config::standard())?;
+
+        Ok(value)
+    }
+
+    /// Offset of the restart point number `index`.
+    fn restart(&self, index: usize) -> Result<usize, Box<dyn Error>> {
+        let restart = read_u32(&self.block, self.end + index * RESTART_SIZE) as usize;
+
+        if restart >= self.end {
+            return Err("restart point of a data block is out of bounds".into());
+        }
+
+        Ok(restart)
+    }
+
+    /// Key of the pair at the restart point number `index`, which is always stored whole.
+    fn restart_key(&self, index: usize) -> Result<K, Box<dyn Error>> {
+        let entry = &self.block[self.restart(index)?..self.end];
+
+        let ((_, key_size, _), header_size): ((usize, usize, usize), _) =
+            bincode::decode_from_slice(entry, bincode::config::standard())?;
+
+        let key = header_size
+            .checked_add(key_size)
+            .and_then(|key_end| entry.get(header_size..key_end))
+            .ok_or("corrupted pair in a data block")?;
+
+        self.decode_key(key)
+    }
+
+    /// Decodes a key as the block's format stores it.
+    fn decode_key(&self, key: &[u8]) -> Result<K, Box<dyn Error>> {
+        let key = match self.format {
+            BlockFormat::PrefixCompressed => Cow::Owned(length_first(key)?),
+            BlockFormat::Plain | BlockFormat::EncodedPrefixCompressed => Cow::Borrowed(key),
+        };
+
+        let (key, _) = bincode::decode_from_slice(&key, bincode::config::standard())?;
+
+        Ok(key)
+    }
+}
+
+/// Lays out an encoded key as [`BlockFormat::PrefixCompressed`] stores it: the size of the
+/// varint the encoding starts with, the rest of the encoding and then the varint. For strings
+/// and other sequences that varint is the length, which would otherwise keep keys of different
+/// lengths from sharing any prefix.
+fn length_last(mut key: Vec<u8>) -> Vec<u8> {
+    let varint_size = match key.first() {
+        Some(0..=250) => 1,
+        Some(251) => 3,
+        Some(252) => 5,
+        Some(253) => 9,
+        Some(254) => 17,
+        _ => 0,
+    };
+    let varint_size = if varint_size > key.len() {
+        0
+    } else {
+        varint_size
+    };
+
+    key.rotate_left(varint_size);
+    key.insert(0, varint_size as u8);
+
+    key
+}
+
+/// Restores the encoding of a key laid out by [`length_last`].
+fn length_first(key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (&varint_size, rest) = key.split_first().ok_or("empty key in a data block")?;
+    let split = rest
+        .len()
+        .checked_sub(varint_size as usize)
+        .ok_or("corrupted key in a data block")?;
+
+    let mut key = rest.to_vec();
+    key.rotate_left(split);
+
+    Ok(key)
+}
+
+fn read_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(block[offset..offset + RESTART_SIZE].try_into().unwrap())
+}
@@ -0,0 +1,91 @@
+use crate::lru_cache::LruCache;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Cache of decompressed data blocks which can be shared by many [`SsTable`]s.
+///
+/// Blocks are identified by the id of their table and their offset in it, so every table sharing
+/// a cache must have an id of its own, see
+/// [`SsTable::with_block_cache`](crate::sstable::SsTable::with_block_cache). The capacity is the
+/// total size of the cached blocks in bytes, and the least recently used blocks are evicted first.
+/// Index partitions pinned by the tables count against the capacity as well, see
+/// [`SsTable::pin_index_partitions`](crate::sstable::SsTable::pin_index_partitions), so the
+/// cached blocks make room for them.
+///
+/// [`SsTable`]: crate::sstable::SsTable
+pub struct BlockCache {
+    blocks: Mutex<LruCache<(u64, u64), Block>>,
+    capacity: usize,
+    /// Total size of the pinned index partitions, changed only while `blocks` is locked.
+    pinned: AtomicUsize,
+}
+
+/// Decompressed data block, shared between the cache and its readers.
+pub(super) type Block = Arc<Vec<u8>>;
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: Mutex::new(LruCache::new(capacity)),
+            capacity,
+            pinned: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of block reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.blocks.lock().unwrap().hits()
+    }
+
+    /// Number of block reads which had to go to disk.
+    pub fn misses(&self) -> u64 {
+        self.blocks.lock().unwrap().misses()
+    }
+
+    /// Total size of the cached blocks in bytes.
+    pub fn usage(&self) -> usize {
+        self.blocks.lock().unwrap().usage()
+    }
+
+    /// Total size of the index partitions pinned by tables in bytes.
+    pub fn pinned_usage(&self) -> usize {
+        self.pinned.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn get(&self, table_id: u64, offset: u64) -> Option<Block> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .get(&(table_id, offset))
+            .cloned()
+    }
+
+    pub(super) fn insert(&self, table_id: u64, offset: u64, block: Block) {
+        let size = block.len();
+
+        self.blocks
+            .lock()
+            .unwrap()
+            .insert_with_charge((table_id, offset), block, size);
+    }
+
+    /// Takes `size` bytes of the capacity for a pinned index partition, evicting blocks to make
+    /// room for it. Pinned partitions may take more than the whole capacity, then no blocks are
+    /// cached at all.
+    pub(super) fn pin(&self, size: usize) {
+        let mut blocks = self.blocks.lock().unwrap();
+        let pinned = self.pinned.fetch_add(size, Ordering::SeqCst) + size;
+
+        blocks.set_capacity(self.capacity.saturating_sub(pinned));
+    }
+
+    /// Gives back `size` bytes of the capacity taken by [`BlockCache::pin`].
+    pub(super) fn unpin(&self, size: usize) {
+        let mut blocks = self.blocks.lock().unwrap();
+        let pinned = self.pinned.fetch_sub(size, Ordering::SeqCst) - size;
+
+        blocks.set_capacity(self.capacity.saturating_sub(pinned));
+    }
+}
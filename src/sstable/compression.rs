@@ -0,0 +1,48 @@
+use std::error::Error;
+
+/// Codec used to compress data blocks of an [`SsTable`](crate::sstable::SsTable).
+///
+/// All codecs are always known, so a table can be loaded and its codec reported no matter how the
+/// crate was built. Compressing or decompressing a block with a codec whose cargo feature is
+/// disabled fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, bincode::Encode, bincode::Decode)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Requires the `lz4` feature.
+    Lz4,
+    /// Requires the `zstd` feature.
+    Zstd,
+    /// Requires the `snappy` feature.
+    Snappy,
+}
+
+impl Compression {
+    pub(super) fn compress(self, block: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            Compression::None => Ok(block.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(block)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::bulk::compress(block, 0)?),
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => Ok(snap::raw::Encoder::new().compress_vec(block)?),
+            #[allow(unreachable_patterns)]
+            codec => Err(format!("{codec:?} compression is not enabled").into()),
+        }
+    }
+
+    pub(super) fn decompress(self, block: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            Compression::None => Ok(block),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::decompress_size_prepended(&block)?),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::decode_all(block.as_slice())?),
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => Ok(snap::raw::Decoder::new().decompress_vec(&block)?),
+            #[allow(unreachable_patterns)]
+            codec => Err(format!("{codec:?} compression is not enabled").into()),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::sstable::{
+    BlockCache, BlockHandle, Compression, DataFile, read_block, read_data_block, write_meta_block,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// Index of the data blocks of a table: the first key of every block with its entry.
+pub(super) enum BlockIndex<K> {
+    /// Whole index, loaded along with the table.
+    Full(BTreeMap<K, IndexEntry<K>>),
+    /// Index split into partitions stored as blocks of their own, which are read when a lookup
+    /// needs them. Only the first key and the location of every partition stay in memory.
+    Partitioned(BTreeMap<K, BlockHandle>),
+}
+
+/// What the index knows about a data block besides its first key.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub(super) struct IndexEntry<K> {
+    /// Last key of the block, so lookups of keys between two blocks don't read either of them.
+    /// Tables of format versions before 3 don't store it.
+    pub(super) last_key: Option<K>,
+    pub(super) handle: BlockHandle,
+}
+
+impl<K> From<BlockHandle> for IndexEntry<K> {
+    /// Entry of a block of a table which stores only the locations of its blocks.
+    fn from(handle: BlockHandle) -> Self {
+        Self {
+            last_key: None,
+            handle,
+        }
+    }
+}
+
+/// How the entries of an index are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum IndexFormat {
+    /// Only the location of every block, as tables of format versions 1 and 2 store it.
+    Handles,
+    /// [`IndexEntry`] of every block.
+    Entries,
+}
+
+/// Entries of an index by the first keys of their data blocks, in ascending order.
+pub(super) type IndexEntries<K> = Vec<(K, IndexEntry<K>)>;
+
+/// Entries of an index partition.
+pub(super) type IndexPartition<K> = Arc<IndexEntries<K>>;
+
+/// Index partitions a table keeps in memory once they're read, by their offsets. Their size is
+/// charged against the capacity of the table's block cache for as long as they're pinned.
+pub(super) struct PinnedPartitions<K> {
+    partitions: Mutex<HashMap<u64, IndexPartition<K>>>,
+    block_cache: Option<Arc<BlockCache>>,
+    /// Size of the pinned partitions as stored, charged against `block_cache`.
+    size: AtomicUsize,
+}
+
+impl<K> PinnedPartitions<K> {
+    pub(super) fn new(block_cache: Option<Arc<BlockCache>>) -> Self {
+        Self {
+            partitions: Mutex::new(HashMap::new()),
+            block_cache,
+            size: AtomicUsize::new(0),
+        }
+    }
+
+    fn get(&self, offset: u64) -> Option<IndexPartition<K>> {
+        self.partitions.lock().unwrap().get(&offset).cloned()
+    }
+
+    /// Pins `partition`, stored in `size` bytes, unless a concurrent read has pinned it already.
+    fn insert(&self, offset: u64, partition: IndexPartition<K>, size: usize) {
+        if self
+            .partitions
+            .lock()
+            .unwrap()
+            .insert(offset, partition)
+            .is_some()
+        {
+            return;
+        }
+
+        self.size.fetch_add(size, Ordering::SeqCst);
+
+        if let Some(block_cache) = &self.block_cache {
+            block_cache.pin(size);
+        }
+    }
+}
+
+impl<K> Drop for PinnedPartitions<K> {
+    fn drop(&mut self) {
+        if let Some(block_cache) = &self.block_cache {
+            block_cache.unpin(*self.size.get_mut());
+        }
+    }
+}
+
+/// Writes the entries of `block_index` into partitions of about `partition_bytes` bytes each, and
+/// returns the first key and the location of every partition.
+pub(super) fn write_index_partitions<K>(
+    writer: &mut BufWriter<File>,
+    block_index: &BTreeMap<K, IndexEntry<K>>,
+    partition_bytes: usize,
+) -> Result<BTreeMap<K, BlockHandle>, Box<dyn Error>>
+where
+    K: Clone + Ord + bincode::Encode,
+{
+    let mut partitions = BTreeMap::new();
+    let mut partition = Vec::new();
+    let mut partition_size = 0;
+
+    for (key, entry) in block_index {
+        partition_size += bincode::encode_to_vec((key, entry), bincode::config::standard())?.len();
+        partition.push((key.clone(), entry.clone()));
+
+        if partition_size >= partition_bytes {
+            let handle = write_meta_block(writer, &partition)?;
+            partitions.insert(partition[0].0.clone(), handle);
+
+            partition.clear();
+            partition_size = 0;
+        }
+    }
+
+    if let Some((first_key, _)) = partition.first() {
+        partitions.insert(first_key.clone(), write_meta_block(writer, &partition)?);
+    }
+
+    Ok(partitions)
+}
+
+/// Returns the index partition at `handle` from `pinned` if the table pins its partitions,
+/// reading and pinning it on first use, or else reads it through the block cache.
+pub(super) fn read_index_partition<K>(
+    file: &DataFile,
+    block_cache: &Option<(u64, Arc<BlockCache>)>,
+    pinned: &Option<Arc<PinnedPartitions<K>>>,
+    handle: &BlockHandle,
+    format: IndexFormat,
+    fill_cache: bool,
+) -> Result<IndexPartition<K>, Box<dyn Error>>
+where
+    K: bincode::Decode<()>,
+{
+    let Some(pinned) = pinned else {
+        let block = read_data_block(block_cache, handle, fill_cache, || {
+            read_block(file, handle, Compression::None)
+        })?;
+
+        return Ok(Arc::new(decode_entries(&block, format)?));
+    };
+
+    if let Some(partition) = pinned.get(handle.offset) {
+        return Ok(partition);
+    }
+
+    // Pinned partitions are charged against the block cache already, so they don't go into it.
+    let block = read_block(file, handle, Compression::None)?;
+    let partition = Arc::new(decode_entries(&block, format)?);
+    pinned.insert(handle.offset, partition.clone(), block.len());
+
+    Ok(partition)
+}
+
+/// Decodes the entries of an index, stored whole or as a partition, in the given format.
+pub(super) fn decode_entries<K>(
+    block: &[u8],
+    format: IndexFormat,
+) -> Result<IndexEntries<K>, Box<dyn Error>>
+where
+    K: bincode::Decode<()>,
+{
+    let entries = match format {
+        IndexFormat::Handles => {
+            let (handles, _): (Vec<(K, BlockHandle)>, _) =
+                bincode::decode_from_slice(block, bincode::config::standard())?;
+
+            handles
+                .into_iter()
+                .map(|(key, handle)| (key, handle.into()))
+                .collect()
+        }
+        IndexFormat::Entries => bincode::decode_from_slice(block, bincode::config::standard())?.0,
+    };
+
+    Ok(entries)
+}
+
+/// Number of entries of `partition` to skip so the first remaining data block may hold `key`.
+pub(super) fn blocks_before<K>(partition: &[(K, IndexEntry<K>)], key: &K) -> usize
+where
+    K: Ord,
+{
+    partition
+        .partition_point(|(first_key, _)| first_key <= key)
+        .saturating_sub(1)
+}
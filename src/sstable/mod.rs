@@ -1,45 +1,360 @@
+mod block;
+mod block_cache;
+mod compression;
+mod index;
+mod range_tombstone;
 #[cfg(test)]
 mod tests;
 
+pub use block_cache::BlockCache;
+pub use compression::Compression;
+pub use range_tombstone::RangeTombstone;
+
+use block::{BlockBuilder, BlockFormat, BlockIter};
+use block_cache::Block;
+use index::{BlockIndex, IndexEntries, IndexEntry, IndexFormat, IndexPartition, PinnedPartitions};
+
 use crate::bloom_filter::BloomFilter;
 use std::{
     collections::BTreeMap,
     error::Error,
+    fmt::{self, Display, Formatter},
     fs::File,
     hash::Hash,
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write},
     marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    os::unix::fs::FileExt,
+    sync::Arc,
 };
 
+/// Size of the CRC32C trailer which follows every block and every serialized structure.
+const CHECKSUM_SIZE: u64 = 4;
+
+/// Magic number closing every single-file table.
+const MAGIC: u64 = 0x5353_5441_424c_4521;
+
+/// Version of the single-file layout written by [`SsTable::new`]. Version 1 stored keys of data
+/// blocks whole, versions 2 to 4 shared prefixes of keys encoded with their lengths first,
+/// versions 1 and 2 indexed blocks only by their first keys, and versions 1 to 3 had no checksum
+/// over the footer.
+const FORMAT_VERSION: u32 = 5;
+
+/// Size of the footer: handle of the meta-index block, its checksum, format version and magic
+/// number.
+const FOOTER_SIZE: u64 = 8 + 8 + CHECKSUM_SIZE + 4 + 8;
+
+/// Size of the footer of versions 1 to 3, which lacks the checksum.
+const UNCHECKSUMMED_FOOTER_SIZE: u64 = 8 + 8 + 4 + 8;
+
+const BLOOM_FILTER_BLOCK: &str = "bloom_filter";
+const INDEX_BLOCK: &str = "index";
+const PARTITIONED_INDEX_BLOCK: &str = "partitioned_index";
+const PROPERTIES_BLOCK: &str = "properties";
+const RANGE_TOMBSTONES_BLOCK: &str = "range_tombstones";
+
+/// Sorted, immutable table of key-value pairs stored on disk.
+///
+/// A table is a single `{path}.sst` file laid out as:
+///
+/// - data blocks of about `block_bytes` bytes, every block compressed on its own and followed by a
+///   CRC32C checksum of the stored bytes. Keys within a block are stored without the prefix they
+///   share with the key before them, except at restart points every `restart_interval` pairs,
+///   which lookups binary search;
+/// - meta blocks holding an optional bloom filter of all keys, so lookups of missing keys usually
+///   don't touch the data at all, an index of the first and last key and the location of every
+///   data block, the table's properties and, when there are any, its range tombstones. The index
+///   of a big table may be split into partitions of about `index_partition_bytes` bytes, with a
+///   meta block of the first key of every partition, so only the partitions lookups need are read;
+/// - a meta-index block pointing to the meta blocks by their names;
+/// - a fixed-size footer with the location of the meta-index block, a CRC32C checksum of it and
+///   of the format version, the format version and a magic number.
+///
+/// Meta blocks are checksummed just like data blocks, and any mismatch is reported as a
+/// [`Corruption`] error. Tables written in the older layout of three files (`{path}.data`,
+/// `{path}.idx` and `{path}.bloom`) can still be loaded. Those with checksummed blocks are read
+/// as they are, so they can be migrated by compaction. Those of the first encoding, with pairs
+/// written one after another and nothing checksummed, are rewritten as a single file on load.
+///
+/// A table keeps its meta blocks in memory and the data file open for as long as it lives, so a
+/// point lookup reads at most one data block. Data blocks may also be kept in a [`BlockCache`]
+/// shared with other tables.
 pub struct SsTable<K, V> {
-    bloom_filter: BloomFilter<K>,
-    block_index: BTreeMap<K, u64>,
-    table_data_path: String,
+    bloom_filter: Option<BloomFilter<K>>,
+    block_index: BlockIndex<K>,
+    /// Index partitions kept in memory once read, if the table pins them.
+    pinned_partitions: Option<Arc<PinnedPartitions<K>>>,
+    properties: SsTableProperties,
+    range_tombstones: Vec<RangeTombstone<K>>,
+    data_file: Arc<DataFile>,
+    /// Id of the table within the block cache and the cache itself.
+    block_cache: Option<(u64, Arc<BlockCache>)>,
+    layout: Layout,
+    block_format: BlockFormat,
+    index_format: IndexFormat,
     _marker: PhantomData<V>,
 }
 
+/// How the files of a table are laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// Everything is in `{path}.sst`.
+    SingleFile,
+    /// Data blocks are in `{path}.data`, the properties and the index in `{path}.idx` and the
+    /// bloom filter in `{path}.bloom`.
+    Legacy,
+}
+
+/// Settings used to build an [`SsTable`].
+#[derive(Debug, Clone)]
+pub struct SsTableOptions {
+    /// Number of pairs after which a block is closed, unless it reaches `block_bytes` first. Not
+    /// limited by default.
+    pub block_size: usize,
+    /// Size in bytes of the encoded pairs after which a block is closed. A pair bigger than that
+    /// gets a block of its own.
+    pub block_bytes: usize,
+    /// Number of pairs from one key stored whole to the next one within a block. Longer intervals
+    /// make tables smaller and lookups within a block slower.
+    pub restart_interval: usize,
+    /// Size in bytes after which a partition of the index is closed. The index is stored whole,
+    /// and loaded whole along with the table, if it's not set.
+    pub index_partition_bytes: Option<usize>,
+    pub compression: Compression,
+    pub bloom_filter: BloomFilterPolicy,
+}
+
+impl Default for SsTableOptions {
+    fn default() -> Self {
+        Self {
+            block_size: usize::MAX,
+            block_bytes: 4 * 1024,
+            restart_interval: 16,
+            index_partition_bytes: None,
+            compression: Compression::None,
+            bloom_filter: BloomFilterPolicy::default(),
+        }
+    }
+}
+
+impl SsTableOptions {
+    /// Fails for settings no table can be built with.
+    pub(crate) fn check(&self) -> Result<(), Box<dyn Error>> {
+        self.bloom_filter.check()
+    }
+}
+
+/// How the bloom filter of an [`SsTable`] is sized.
+#[derive(Debug, Clone, Copy, PartialEq, bincode::Encode, bincode::Decode)]
+pub enum BloomFilterPolicy {
+    /// The table has no bloom filter, so every lookup reads a data block.
+    Disabled,
+    /// Expected probability of a false positive answer, above 0 and below 1.
+    FalsePositiveRate(f64),
+    /// Number of filter bits spent on every key.
+    BitsPerKey(usize),
+}
+
+impl Default for BloomFilterPolicy {
+    fn default() -> Self {
+        BloomFilterPolicy::FalsePositiveRate(0.01)
+    }
+}
+
+impl BloomFilterPolicy {
+    fn check(self) -> Result<(), Box<dyn Error>> {
+        // Also rejects NaN, which fails every comparison.
+        if let BloomFilterPolicy::FalsePositiveRate(rate) = self
+            && !(rate > 0.0 && rate < 1.0)
+        {
+            return Err(format!(
+                "bloom filter false positive rate must be between 0 and 1, not {rate}"
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn build<K>(self, keys: usize) -> Option<BloomFilter<K>> {
+        match self {
+            BloomFilterPolicy::Disabled => None,
+            BloomFilterPolicy::FalsePositiveRate(rate) => Some(BloomFilter::new(keys, rate)),
+            BloomFilterPolicy::BitsPerKey(bits) => Some(BloomFilter::with_bits_per_key(keys, bits)),
+        }
+    }
+}
+
+/// Settings an [`SsTable`] was built with and a summary of its content, stored within the table.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct SsTableProperties {
+    pub compression: Compression,
+    pub bloom_filter: BloomFilterPolicy,
+    /// Size of all data blocks, which start at the beginning of the data file.
+    pub data_size: u64,
+}
+
+/// Location of a block in the data file. The size is the one of the stored (possibly compressed)
+/// block and does not include the checksum trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+/// File holding the data blocks of a table. Blocks are read at their offsets, so readers on many
+/// threads share the file without waiting for each other.
+struct DataFile {
+    file: File,
+    path: String,
+    /// Size of the file, which never changes, so damaged handles are caught without asking the
+    /// file system.
+    size: u64,
+}
+
+impl DataFile {
+    fn open(path: String) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self { file, path, size })
+    }
+}
+
+/// Error returned when a part of a table does not match its checksum or is cut short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    /// File holding the damaged part.
+    pub file: String,
+    /// Position in the file where the damaged part starts.
+    pub offset: u64,
+}
+
+impl Display for Corruption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "corrupted data in {} at offset {}",
+            self.file, self.offset
+        )
+    }
+}
+
+impl Error for Corruption {}
+
+/// Iterator over the key-value pairs of an [`SsTable`] which fall into a range of keys, in
+/// ascending order of keys.
 pub struct SsTableIter<K, V> {
-    reader: BufReader<File>,
-    phantom_data: PhantomData<(K, V)>,
+    data_file: Arc<DataFile>,
+    compression: Compression,
+    block_cache: Option<(u64, Arc<BlockCache>)>,
+    fill_cache: bool,
+    blocks: std::vec::IntoIter<BlockHandle>,
+    /// Index partitions holding the blocks after `blocks`, if the index is partitioned.
+    partitions: std::vec::IntoIter<BlockHandle>,
+    pinned_partitions: Option<Arc<PinnedPartitions<K>>>,
+    index_format: IndexFormat,
+    block_format: BlockFormat,
+    block: Option<BlockIter<K, V>>,
+    start: Bound<K>,
+    end: Bound<K>,
+    phantom_data: PhantomData<V>,
+}
+
+impl<K, V> SsTableIter<K, V>
+where
+    K: Ord + bincode::Decode<()>,
+    V: bincode::Decode<()>,
+{
+    /// Sets whether blocks read from disk are put into the block cache of the table, which they
+    /// are by default. A long scan, like the one of a compaction, would otherwise push the blocks
+    /// of frequent point lookups out of the cache.
+    pub fn fill_cache(mut self, fill_cache: bool) -> Self {
+        self.fill_cache = fill_cache;
+        self
+    }
+
+    fn next_pair(&mut self) -> Result<Option<(K, V)>, Box<dyn Error>> {
+        loop {
+            if let Some(block) = &mut self.block
+                && let Some(pair) = block.next_pair()?
+            {
+                return Ok(Some(pair));
+            }
+
+            let Some(handle) = self.blocks.next() else {
+                let Some(partition) = self.partitions.next() else {
+                    return Ok(None);
+                };
+
+                self.blocks = index::read_index_partition(
+                    &self.data_file,
+                    &self.block_cache,
+                    &self.pinned_partitions,
+                    &partition,
+                    self.index_format,
+                    self.fill_cache,
+                )?
+                .iter()
+                .map(|(_, entry)| entry.handle)
+                .collect::<Vec<_>>()
+                .into_iter();
+
+                continue;
+            };
+
+            let block = read_data_block(&self.block_cache, &handle, self.fill_cache, || {
+                read_block(&self.data_file, &handle, self.compression)
+            })?;
+            let mut block = BlockIter::new(block, self.block_format)?;
+
+            // Only the first block may hold keys before the start of the range.
+            if self.block.is_none()
+                && let Bound::Included(start) | Bound::Excluded(start) = &self.start
+            {
+                block.seek(start)?;
+            }
+
+            self.block = Some(block);
+        }
+    }
 }
 
 impl<K, V> Iterator for SsTableIter<K, V>
 where
-    K: bincode::Decode<()>,
+    K: Ord + bincode::Decode<()>,
     V: bincode::Decode<()>,
 {
     type Item = Result<(K, V), Box<dyn Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = bincode::decode_from_reader::<(K, V), _, _>(
-            &mut self.reader,
-            bincode::config::standard(),
-        );
+        loop {
+            let (key, value) = match self.next_pair() {
+                Ok(pair) => pair?,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let before_start = match &self.start {
+                Bound::Included(start) => &key < start,
+                Bound::Excluded(start) => &key <= start,
+                Bound::Unbounded => false,
+            };
+
+            if before_start {
+                continue;
+            }
+
+            let after_end = match &self.end {
+                Bound::Included(end) => &key > end,
+                Bound::Excluded(end) => &key >= end,
+                Bound::Unbounded => false,
+            };
 
-        match result {
-            Ok((key, value)) => Some(Ok((key, value))),
-            Err(e) if e.to_string().contains("UnexpectedEof") => None,
-            Err(e) => Some(Err(Box::new(e))),
+            if after_end {
+                return None;
+            }
+
+            return Some(Ok((key, value)));
         }
     }
 }
@@ -52,139 +367,814 @@ where
     pub fn new(
         data: BTreeMap<K, V>,
         table_path: &str,
-        block_size: usize,
+        options: &SsTableOptions,
     ) -> Result<Self, Box<dyn Error>> {
-        let mut data_writer = BufWriter::new(File::create(format!("{table_path}.data"))?);
+        Self::with_range_tombstones(data, Vec::new(), table_path, options)
+    }
 
-        let mut bloom_filter = BloomFilter::new(data.len(), 0.1);
-        let mut block_index: BTreeMap<K, u64> = BTreeMap::new();
+    /// Builds a table which also stores `range_tombstones` in a meta block of its own. The table
+    /// doesn't apply them to its pairs, that's up to its reader.
+    pub fn with_range_tombstones(
+        data: BTreeMap<K, V>,
+        range_tombstones: Vec<RangeTombstone<K>>,
+        table_path: &str,
+        options: &SsTableOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        options.check()?;
 
-        let mut block = Vec::new();
-        let mut block_index_key = None;
+        let mut properties = SsTableProperties {
+            compression: options.compression,
+            bloom_filter: options.bloom_filter,
+            data_size: 0,
+        };
 
-        for (i, (key, value)) in data.into_iter().enumerate() {
-            bloom_filter.add(key.clone());
+        // The table is written under a temporary name, so a table file is either complete or
+        // missing, even when the process dies in the middle of writing it.
+        let table_data_path = format!("{table_path}.sst");
+        let temp_path = format!("{table_data_path}.tmp");
+        let mut data_writer = BufWriter::new(File::create(&temp_path)?);
 
-            if block_index_key.is_none() {
-                block_index_key = Some(key.clone());
-            }
+        let mut bloom_filter = options.bloom_filter.build(data.len());
+        let mut block_index = BTreeMap::new();
 
-            if i % block_size == 0 {
-                let pos = data_writer.stream_position()?;
-                block_index.insert(block_index_key.unwrap(), pos);
+        let mut block = BlockBuilder::new(options.restart_interval);
+        // First and last key of the pairs added to `block` so far.
+        let mut block_keys: Option<(K, K)> = None;
+        let mut block_entries = 0;
 
-                data_writer.write_all(&block)?;
-                block.clear();
+        // Writes the block once it has all its pairs, and indexes it by the keys it starts and
+        // ends with and by the bytes it takes.
+        let mut close_block = |block: &mut BlockBuilder, (first_key, last_key): (K, K)| {
+            let handle = write_block(&mut data_writer, &block.finish(), options.compression)?;
+            let entry = IndexEntry {
+                last_key: Some(last_key),
+                handle,
+            };
+            block_index.insert(first_key, entry);
 
-                block_index_key = None;
+            Ok::<_, Box<dyn Error>>(())
+        };
+
+        for (key, value) in data {
+            if let Some(bloom_filter) = &mut bloom_filter {
+                bloom_filter.add(key.clone());
+            }
+
+            block.add(&key, &value)?;
+            block_entries += 1;
+
+            block_keys = match block_keys.take() {
+                Some((first_key, _)) => Some((first_key, key)),
+                None => Some((key.clone(), key)),
             };
 
-            let data = bincode::encode_to_vec(&(key, value), bincode::config::standard())?;
-            block.extend(&data);
+            if block_entries == options.block_size || block.size() >= options.block_bytes {
+                close_block(&mut block, block_keys.take().unwrap())?;
+                block_entries = 0;
+            }
         }
 
-        if !block.is_empty() {
-            data_writer.write_all(&block)?;
-            block.clear();
+        if let Some(keys) = block_keys {
+            close_block(&mut block, keys)?;
         }
 
-        Self::serialize_on_disk(&block_index, format!("{table_path}.idx"))?;
-        Self::serialize_on_disk(&bloom_filter, format!("{table_path}.bloom"))?;
+        properties.data_size = data_writer.stream_position()?;
+
+        let mut meta_index = BTreeMap::new();
+
+        if let Some(bloom_filter) = &bloom_filter {
+            meta_index.insert(
+                BLOOM_FILTER_BLOCK.to_string(),
+                write_meta_block(&mut data_writer, bloom_filter)?,
+            );
+        }
+
+        let block_index = match options.index_partition_bytes {
+            Some(partition_bytes) => {
+                let partitions =
+                    index::write_index_partitions(&mut data_writer, &block_index, partition_bytes)?;
+                meta_index.insert(
+                    PARTITIONED_INDEX_BLOCK.to_string(),
+                    write_meta_block(&mut data_writer, &partitions)?,
+                );
+
+                BlockIndex::Partitioned(partitions)
+            }
+            None => {
+                meta_index.insert(
+                    INDEX_BLOCK.to_string(),
+                    write_meta_block(&mut data_writer, &block_index)?,
+                );
+
+                BlockIndex::Full(block_index)
+            }
+        };
+        meta_index.insert(
+            PROPERTIES_BLOCK.to_string(),
+            write_meta_block(&mut data_writer, &properties)?,
+        );
+
+        if !range_tombstones.is_empty() {
+            meta_index.insert(
+                RANGE_TOMBSTONES_BLOCK.to_string(),
+                write_meta_block(&mut data_writer, &range_tombstones)?,
+            );
+        }
+
+        let meta_index_handle = write_meta_block(&mut data_writer, &meta_index)?;
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE as usize);
+        footer.extend_from_slice(&meta_index_handle.offset.to_le_bytes());
+        footer.extend_from_slice(&meta_index_handle.size.to_le_bytes());
+        let checksum = footer_checksum(&footer, FORMAT_VERSION);
+        footer.extend_from_slice(&checksum.to_le_bytes());
+        footer.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data_writer.write_all(&footer)?;
+        data_writer.write_all(&MAGIC.to_le_bytes())?;
+
+        data_writer.flush()?;
+        data_writer.get_ref().sync_all()?;
+        drop(data_writer);
+
+        std::fs::rename(&temp_path, &table_data_path)?;
+        sync_parent_directory(&table_data_path)?;
+
+        let data_file = Arc::new(DataFile::open(table_data_path)?);
 
         Ok(Self {
             bloom_filter,
             block_index,
-            table_data_path: format!("{table_path}.data"),
+            pinned_partitions: None,
+            properties,
+            range_tombstones,
+            data_file,
+            block_cache: None,
+            layout: Layout::SingleFile,
+            block_format: BlockFormat::PrefixCompressed,
+            index_format: IndexFormat::Entries,
             _marker: Default::default(),
         })
     }
 
     pub fn load(table_path: String) -> Result<Self, Box<dyn Error>> {
-        let block_index = {
-            let mut index_reader = BufReader::new(File::open(format!("{table_path}.idx"))?);
-            let mut index_buf = Vec::new();
-            index_reader.read_to_end(&mut index_buf)?;
+        let table_data_path = format!("{table_path}.sst");
+
+        if !std::fs::exists(&table_data_path)? {
+            return Self::load_legacy(table_path);
+        }
+
+        Self::load_single_file(table_data_path)
+    }
+
+    fn load_single_file(table_data_path: String) -> Result<Self, Box<dyn Error>> {
+        let data_file = DataFile::open(table_data_path.clone())?;
 
-            let (block_index, _) =
-                bincode::decode_from_slice(&index_buf, bincode::config::standard())?;
+        let corruption = |offset| -> Box<dyn Error> {
+            Box::new(Corruption {
+                file: table_data_path.clone(),
+                offset,
+            })
+        };
 
-            block_index
+        let footer_offset = |footer_size| {
+            data_file
+                .size
+                .checked_sub(footer_size)
+                .ok_or_else(|| corruption(0))
         };
 
-        let bloom_filter = {
-            let mut bloom_reader = BufReader::new(File::open(format!("{table_path}.bloom"))?);
-            let mut index_buf = Vec::new();
-            bloom_reader.read_to_end(&mut index_buf)?;
+        // The format version and the magic number close the footer of every version.
+        let mut trailer = [0; 4 + 8];
+        let trailer_offset = footer_offset(trailer.len() as u64)?;
+        data_file.file.read_exact_at(&mut trailer, trailer_offset)?;
 
-            let (bloom_filter, _) =
-                bincode::decode_from_slice(&index_buf, bincode::config::standard())?;
+        let version = u32::from_le_bytes(trailer[0..4].try_into()?);
+        let magic = u64::from_le_bytes(trailer[4..12].try_into()?);
+
+        if magic != MAGIC {
+            return Err(corruption(trailer_offset + 4));
+        }
 
-            bloom_filter
+        let (block_format, index_format) = match version {
+            1 => (BlockFormat::Plain, IndexFormat::Handles),
+            2 => (BlockFormat::EncodedPrefixCompressed, IndexFormat::Handles),
+            3 | 4 => (BlockFormat::EncodedPrefixCompressed, IndexFormat::Entries),
+            FORMAT_VERSION => (BlockFormat::PrefixCompressed, IndexFormat::Entries),
+            _ => {
+                return Err(format!(
+                    "unsupported SsTable format version {version} in {table_data_path}"
+                )
+                .into());
+            }
         };
 
+        let checksummed = version >= 4;
+        let footer_offset = footer_offset(if checksummed {
+            FOOTER_SIZE
+        } else {
+            UNCHECKSUMMED_FOOTER_SIZE
+        })?;
+
+        let mut footer = [0; FOOTER_SIZE as usize];
+        data_file.file.read_exact_at(
+            &mut footer[..(data_file.size - footer_offset) as usize],
+            footer_offset,
+        )?;
+
+        if checksummed
+            && footer_checksum(&footer[0..16], version).to_le_bytes()[..] != footer[16..20]
+        {
+            return Err(corruption(footer_offset));
+        }
+
+        let meta_index_handle = BlockHandle {
+            offset: u64::from_le_bytes(footer[0..8].try_into()?),
+            size: u64::from_le_bytes(footer[8..16].try_into()?),
+        };
+
+        // Footers of older versions aren't checksummed, so their handle is at least kept in front
+        // of the footer.
+        meta_index_handle
+            .offset
+            .checked_add(meta_index_handle.size)
+            .and_then(|end| end.checked_add(CHECKSUM_SIZE))
+            .filter(|&end| end <= footer_offset)
+            .ok_or_else(|| corruption(footer_offset))?;
+
+        let meta_index: BTreeMap<String, BlockHandle> =
+            read_meta_block(&data_file, &meta_index_handle)?;
+
+        let meta_block_handle = |name| {
+            meta_index
+                .get(name)
+                .ok_or_else(|| corruption(meta_index_handle.offset))
+        };
+
+        let bloom_filter = meta_index
+            .get(BLOOM_FILTER_BLOCK)
+            .map(|handle| read_meta_block(&data_file, handle))
+            .transpose()?;
+        let block_index = match meta_index.get(PARTITIONED_INDEX_BLOCK) {
+            Some(handle) => BlockIndex::Partitioned(read_meta_block(&data_file, handle)?),
+            None => {
+                let index = read_block(
+                    &data_file,
+                    meta_block_handle(INDEX_BLOCK)?,
+                    Compression::None,
+                )?;
+
+                BlockIndex::Full(
+                    index::decode_entries(&index, index_format)?
+                        .into_iter()
+                        .collect(),
+                )
+            }
+        };
+        let properties = read_meta_block(&data_file, meta_block_handle(PROPERTIES_BLOCK)?)?;
+        let range_tombstones = meta_index
+            .get(RANGE_TOMBSTONES_BLOCK)
+            .map(|handle| read_meta_block(&data_file, handle))
+            .transpose()?
+            .unwrap_or_default();
+
         Ok(Self {
             bloom_filter,
             block_index,
-            table_data_path: format!("{table_path}.data"),
+            pinned_partitions: None,
+            properties,
+            range_tombstones,
+            data_file: Arc::new(data_file),
+            block_cache: None,
+            layout: Layout::SingleFile,
+            block_format,
+            index_format,
+            _marker: Default::default(),
+        })
+    }
+
+    fn load_legacy(table_path: String) -> Result<Self, Box<dyn Error>> {
+        // Only the first encoding of the layout has no checksum after the index.
+        if checksummed_payload(&std::fs::read(format!("{table_path}.idx"))?).is_none() {
+            return Self::migrate_unchecksummed(table_path);
+        }
+
+        let (compression, block_index): (_, BTreeMap<K, BlockHandle>) =
+            Self::deserialize_from_disk(format!("{table_path}.idx"))?;
+        let block_index = block_index
+            .into_iter()
+            .map(|(key, handle)| (key, handle.into()))
+            .collect();
+        let bloom_filter = Self::deserialize_from_disk(format!("{table_path}.bloom"))?;
+
+        let data_file = DataFile::open(format!("{table_path}.data"))?;
+
+        Ok(Self {
+            bloom_filter: Some(bloom_filter),
+            block_index: BlockIndex::Full(block_index),
+            pinned_partitions: None,
+            properties: SsTableProperties {
+                compression,
+                bloom_filter: BloomFilterPolicy::FalsePositiveRate(0.1),
+                data_size: data_file.size,
+            },
+            range_tombstones: Vec::new(),
+            data_file: Arc::new(data_file),
+            block_cache: None,
+            layout: Layout::Legacy,
+            block_format: BlockFormat::Plain,
+            index_format: IndexFormat::Handles,
             _marker: Default::default(),
         })
     }
 
+    /// Rewrites a table of the first encoding of the three-file layout as a single file. That
+    /// encoding has no blocks to speak of, and it was only written for tables built from a
+    /// memtable, so the pairs are read into memory all at once.
+    fn migrate_unchecksummed(table_path: String) -> Result<Self, Box<dyn Error>> {
+        let corruption = |file: &str, offset| -> Box<dyn Error> {
+            Box::new(Corruption {
+                file: format!("{table_path}.{file}"),
+                offset,
+            })
+        };
+
+        // The index maps keys to offsets of pairs. The pairs get indexed anew, so it only tells
+        // the encoding apart from a damaged index of the later one.
+        let index = std::fs::read(format!("{table_path}.idx"))?;
+
+        match bincode::decode_from_slice::<BTreeMap<K, u64>, _>(&index, bincode::config::standard())
+        {
+            Ok((_, size)) if size == index.len() => {}
+            _ => return Err(corruption("idx", 0)),
+        }
+
+        let data = std::fs::read(format!("{table_path}.data"))?;
+        let mut pairs = BTreeMap::new();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let ((key, value), size): ((K, V), _) =
+                bincode::decode_from_slice(&data[offset..], bincode::config::standard())
+                    .map_err(|_| corruption("data", offset as u64))?;
+
+            if pairs
+                .last_key_value()
+                .is_some_and(|(last_key, _)| last_key >= &key)
+            {
+                return Err(corruption("data", offset as u64));
+            }
+
+            pairs.insert(key, value);
+            offset += size;
+        }
+
+        let table = Self::new(pairs, &table_path, &SsTableOptions::default())?;
+
+        for extension in ["data", "idx", "bloom"] {
+            std::fs::remove_file(format!("{table_path}.{extension}"))?;
+        }
+
+        Ok(table)
+    }
+
+    /// Makes the table keep its data blocks in `block_cache`, where they are identified by
+    /// `table_id`. Tables sharing a cache must have different ids.
+    pub fn with_block_cache(mut self, table_id: u64, block_cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some((table_id, block_cache));
+
+        // Nothing is pinned before the table is first read, so pinning may start over.
+        if self.pinned_partitions.is_some() {
+            self = self.pin_index_partitions();
+        }
+
+        self
+    }
+
+    /// Makes the table keep the partitions of its index in memory once they're read, instead of
+    /// reading them through the block cache on every lookup. Doesn't matter for tables whose index
+    /// isn't partitioned. Pinned partitions take their size from the capacity of the table's
+    /// block cache until the table is dropped; a table without a block cache pins them without
+    /// any limit.
+    pub fn pin_index_partitions(mut self) -> Self {
+        let block_cache = self.block_cache.as_ref().map(|(_, cache)| cache.clone());
+        self.pinned_partitions = Some(Arc::new(PinnedPartitions::new(block_cache)));
+        self
+    }
+
     pub fn get(&self, key: &K) -> Result<Option<V>, Box<dyn Error>> {
-        if !self.bloom_filter.contains(key) {
+        if let Some(bloom_filter) = &self.bloom_filter
+            && !bloom_filter.contains(key)
+        {
             return Ok(None);
         }
 
-        let Some((index_key, pos)) = self.block_index.range(..=key.to_owned()).next_back() else {
-            return Ok(None); // index does not cover the value? Maybe it's empty...
+        let Some(IndexEntry { last_key, handle }) = self.find_block(key)? else {
+            return Ok(None); // The key is smaller than any key of the table.
         };
 
-        let stop_search = if key == index_key {
-            None
-        } else {
-            self.block_index
-                .range(key.to_owned()..)
-                .next()
-                .map(|(_, pos)| *pos)
+        if last_key.is_some_and(|last_key| *key > last_key) {
+            return Ok(None); // The key falls between this block and the next one.
+        }
+
+        let block = read_data_block(&self.block_cache, &handle, true, || {
+            read_block(&self.data_file, &handle, self.properties.compression)
+        })?;
+
+        BlockIter::new(block, self.block_format)?.get(key)
+    }
+
+    pub fn iter(&self) -> Result<SsTableIter<K, V>, Box<dyn Error>> {
+        self.range(..)
+    }
+
+    /// Returns an iterator over the pairs whose keys are in `range`. The iterator starts reading
+    /// from the block which may hold the start of the range, not from the beginning of the table.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<SsTableIter<K, V>, Box<dyn Error>> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+
+        let (blocks, partitions) = match &self.block_index {
+            BlockIndex::Full(index) => {
+                let blocks = index
+                    .range((first_holding(index, &start), Bound::Unbounded))
+                    .map(|(_, entry)| entry.handle)
+                    .collect();
+
+                (blocks, Vec::new())
+            }
+            BlockIndex::Partitioned(index) => {
+                let mut partitions: Vec<_> = index
+                    .range((first_holding(index, &start), Bound::Unbounded))
+                    .map(|(_, handle)| *handle)
+                    .collect();
+
+                // Only the partition which may hold the start is read now, the others as the
+                // iterator gets to them.
+                let blocks = match &start {
+                    Bound::Included(key) | Bound::Excluded(key) if !partitions.is_empty() => {
+                        let partition = self.read_index_partition(&partitions.remove(0))?;
+
+                        partition[index::blocks_before(&partition, key)..]
+                            .iter()
+                            .map(|(_, entry)| entry.handle)
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+
+                (blocks, partitions)
+            }
+        };
+
+        Ok(SsTableIter {
+            data_file: self.data_file.clone(),
+            compression: self.properties.compression,
+            block_cache: self.block_cache.clone(),
+            fill_cache: true,
+            blocks: blocks.into_iter(),
+            partitions: partitions.into_iter(),
+            pinned_partitions: self.pinned_partitions.clone(),
+            index_format: self.index_format,
+            block_format: self.block_format,
+            block: None,
+            start,
+            end,
+            phantom_data: Default::default(),
+        })
+    }
+
+    /// Reads the whole table and checks every block against its checksum, that the data blocks
+    /// cover the data part of the file exactly, that the index has the first and last key of
+    /// every block right and that the pairs are sorted.
+    pub fn verify(&self) -> Result<(), Box<dyn Error>> {
+        // The footer and meta blocks get checked by loading the table once again.
+        let data_size = match self.layout {
+            Layout::SingleFile => {
+                Self::load_single_file(self.data_file.path.clone())?
+                    .properties
+                    .data_size
+            }
+            Layout::Legacy => self.data_file.size,
         };
 
-        let mut data_reader = BufReader::new(File::open(&self.table_data_path)?);
+        let mut expected_offset = 0;
+        let mut last_key: Option<K> = None;
 
-        data_reader.seek(SeekFrom::Start(*pos))?;
+        for (first_key, entry) in self.index_entries()? {
+            let handle = entry.handle;
 
-        while stop_search.is_none() || Some(data_reader.stream_position()?) <= stop_search {
-            let result = bincode::decode_from_reader::<(K, V), _, _>(
-                &mut data_reader,
-                bincode::config::standard(),
-            );
+            if handle.offset != expected_offset {
+                return Err(self.corruption(expected_offset));
+            }
 
-            match result {
-                Ok((pos_key, _)) if key != &pos_key => {
-                    continue;
+            let block = read_block(&self.data_file, &handle, self.properties.compression)?;
+            let mut pairs = BlockIter::<K, V>::new(Arc::new(block), self.block_format)
+                .map_err(|_| self.corruption(handle.offset))?;
+
+            let mut block_first_key = None;
+
+            while let Some((key, _)) = pairs
+                .next_entry()
+                .map_err(|_| self.corruption(handle.offset))?
+            {
+                if last_key.as_ref().is_some_and(|last_key| last_key >= &key) {
+                    return Err(self.corruption(handle.offset));
                 }
-                Ok((_, value)) => return Ok(Some(value)),
-                Err(e) if e.to_string().contains("UnexpectedEof") => return Ok(None),
-                Err(e) => return Err(Box::new(e)),
+
+                if block_first_key.is_none() {
+                    block_first_key = Some(key.clone());
+                }
+
+                last_key = Some(key);
             }
+
+            // The index must name the keys the block starts and ends with.
+            if block_first_key.as_ref() != Some(&first_key)
+                || entry
+                    .last_key
+                    .is_some_and(|block_last_key| last_key != Some(block_last_key))
+            {
+                return Err(self.corruption(handle.offset));
+            }
+
+            expected_offset = handle.offset + handle.size + CHECKSUM_SIZE;
+        }
+
+        if data_size != expected_offset {
+            return Err(self.corruption(expected_offset));
         }
 
-        Ok(None)
+        Ok(())
     }
 
-    pub fn iter(&self) -> Result<SsTableIter<K, V>, Box<dyn Error>> {
-        Ok(SsTableIter {
-            reader: BufReader::new(File::open(&self.table_data_path)?),
-            phantom_data: Default::default(),
+    pub fn range_tombstones(&self) -> &[RangeTombstone<K>] {
+        &self.range_tombstones
+    }
+
+    pub fn properties(&self) -> &SsTableProperties {
+        &self.properties
+    }
+
+    /// Size of the table on disk, in bytes.
+    pub fn size(&self) -> Result<u64, Box<dyn Error>> {
+        let mut size = self.data_file.size;
+
+        if self.layout == Layout::Legacy {
+            let table_path = self.data_file.path.trim_end_matches(".data");
+
+            for extension in ["idx", "bloom"] {
+                size += std::fs::metadata(format!("{table_path}.{extension}"))?.len();
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Removes all files of the table stored at `table_path`, in either layout.
+    pub fn remove(table_path: &str) -> Result<(), Box<dyn Error>> {
+        let table_data_path = format!("{table_path}.sst");
+
+        if std::fs::exists(&table_data_path)? {
+            std::fs::remove_file(table_data_path)?;
+
+            // Left behind when the process died while a table was being rewritten on load.
+            for extension in ["data", "idx", "bloom"] {
+                let _ = std::fs::remove_file(format!("{table_path}.{extension}"));
+            }
+
+            return Ok(());
+        }
+
+        for extension in ["data", "idx", "bloom"] {
+            std::fs::remove_file(format!("{table_path}.{extension}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the location of the data block which may hold `key`, reading the index partition
+    /// holding it if the index is partitioned.
+    fn find_block(&self, key: &K) -> Result<Option<IndexEntry<K>>, Box<dyn Error>> {
+        match &self.block_index {
+            BlockIndex::Full(index) => Ok(index
+                .range(..=key.to_owned())
+                .next_back()
+                .map(|(_, entry)| entry.clone())),
+            BlockIndex::Partitioned(index) => {
+                let Some((_, partition)) = index.range(..=key.to_owned()).next_back() else {
+                    return Ok(None);
+                };
+
+                let partition = self.read_index_partition(partition)?;
+
+                Ok(partition
+                    .get(index::blocks_before(&partition, key))
+                    .map(|(_, entry)| entry.clone()))
+            }
+        }
+    }
+
+    fn read_index_partition(
+        &self,
+        handle: &BlockHandle,
+    ) -> Result<IndexPartition<K>, Box<dyn Error>> {
+        index::read_index_partition(
+            &self.data_file,
+            &self.block_cache,
+            &self.pinned_partitions,
+            handle,
+            self.index_format,
+            true,
+        )
+    }
+
+    /// Index entries of all data blocks by their first keys, read from the disk for partitioned
+    /// indexes, whose partitions must start with the keys the top-level index has for them.
+    fn index_entries(&self) -> Result<IndexEntries<K>, Box<dyn Error>> {
+        let index = match &self.block_index {
+            BlockIndex::Full(index) => return Ok(index.clone().into_iter().collect()),
+            BlockIndex::Partitioned(index) => index,
+        };
+
+        let mut entries = Vec::new();
+
+        for (first_key, handle) in index {
+            let partition = read_block(&self.data_file, handle, Compression::None)?;
+            let partition = index::decode_entries(&partition, self.index_format)?;
+
+            if partition.first().map(|(key, _)| key) != Some(first_key) {
+                return Err(self.corruption(handle.offset));
+            }
+
+            entries.extend(partition);
+        }
+
+        Ok(entries)
+    }
+
+    fn corruption(&self, offset: u64) -> Box<dyn Error> {
+        Box::new(Corruption {
+            file: self.data_file.path.clone(),
+            offset,
         })
     }
 
-    fn serialize_on_disk<D>(data: &D, file_name: String) -> Result<(), Box<dyn Error>>
+    fn deserialize_from_disk<D>(file_name: String) -> Result<D, Box<dyn Error>>
     where
-        D: bincode::Encode,
+        D: bincode::Decode<()>,
     {
-        let serialized = bincode::encode_to_vec(data, bincode::config::standard())?;
-        let mut writer = BufWriter::new(File::create(file_name)?);
-        writer.write_all(&serialized)?;
-        Ok(())
+        let mut buf = Vec::new();
+        BufReader::new(File::open(&file_name)?).read_to_end(&mut buf)?;
+
+        let Some(payload) = checksummed_payload(&buf) else {
+            return Err(Box::new(Corruption {
+                file: file_name,
+                offset: 0,
+            }));
+        };
+
+        let (data, _) = bincode::decode_from_slice(payload, bincode::config::standard())?;
+
+        Ok(data)
+    }
+}
+
+/// Returns what `buf` holds before its CRC32C trailer, unless the trailer doesn't match it.
+fn checksummed_payload(buf: &[u8]) -> Option<&[u8]> {
+    let payload_size = buf.len().checked_sub(CHECKSUM_SIZE as usize)?;
+    let (payload, checksum) = buf.split_at(payload_size);
+
+    (crc32c::crc32c(payload).to_le_bytes() == checksum).then_some(payload)
+}
+
+/// First key of the block, or of the index partition, which may hold `start`.
+fn first_holding<K, T>(index: &BTreeMap<K, T>, start: &Bound<K>) -> Bound<K>
+where
+    K: Clone + Ord,
+{
+    match start {
+        Bound::Included(key) | Bound::Excluded(key) => index
+            .range(..=key.to_owned())
+            .next_back()
+            .map(|(first_key, _)| Bound::Included(first_key.to_owned()))
+            .unwrap_or(Bound::Unbounded),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn write_block(
+    writer: &mut BufWriter<File>,
+    block: &[u8],
+    compression: Compression,
+) -> Result<BlockHandle, Box<dyn Error>> {
+    let offset = writer.stream_position()?;
+    let block = compression.compress(block)?;
+
+    writer.write_all(&block)?;
+    writer.write_all(&crc32c::crc32c(&block).to_le_bytes())?;
+
+    Ok(BlockHandle {
+        offset,
+        size: block.len() as u64,
+    })
+}
+
+fn write_meta_block<D>(
+    writer: &mut BufWriter<File>,
+    data: &D,
+) -> Result<BlockHandle, Box<dyn Error>>
+where
+    D: bincode::Encode,
+{
+    let block = bincode::encode_to_vec(data, bincode::config::standard())?;
+    write_block(writer, &block, Compression::None)
+}
+
+fn read_meta_block<D>(file: &DataFile, handle: &BlockHandle) -> Result<D, Box<dyn Error>>
+where
+    D: bincode::Decode<()>,
+{
+    let block = read_block(file, handle, Compression::None)?;
+    let (data, _) = bincode::decode_from_slice(&block, bincode::config::standard())?;
+
+    Ok(data)
+}
+
+/// Returns a data block from the block cache, or reads it with `read` and puts it into the cache
+/// if `fill_cache` is set.
+fn read_data_block(
+    block_cache: &Option<(u64, Arc<BlockCache>)>,
+    handle: &BlockHandle,
+    fill_cache: bool,
+    read: impl FnOnce() -> Result<Vec<u8>, Box<dyn Error>>,
+) -> Result<Block, Box<dyn Error>> {
+    let Some((table_id, block_cache)) = block_cache else {
+        return Ok(Arc::new(read()?));
+    };
+
+    if let Some(block) = block_cache.get(*table_id, handle.offset) {
+        return Ok(block);
     }
+
+    let block = Arc::new(read()?);
+
+    if fill_cache {
+        block_cache.insert(*table_id, handle.offset, block.clone());
+    }
+
+    Ok(block)
+}
+
+/// Checksum of a footer's meta-index handle and format version.
+fn footer_checksum(handle: &[u8], version: u32) -> u32 {
+    crc32c::crc32c_append(crc32c::crc32c(handle), &version.to_le_bytes())
+}
+
+fn read_block(
+    file: &DataFile,
+    handle: &BlockHandle,
+    compression: Compression,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let corruption = || -> Box<dyn Error> {
+        Box::new(Corruption {
+            file: file.path.clone(),
+            offset: handle.offset,
+        })
+    };
+
+    // A damaged handle must not make us allocate more than the file holds.
+    handle
+        .offset
+        .checked_add(handle.size)
+        .and_then(|end| end.checked_add(CHECKSUM_SIZE))
+        .filter(|&end| end <= file.size)
+        .ok_or_else(corruption)?;
+
+    let mut block = vec![0; (handle.size + CHECKSUM_SIZE) as usize];
+
+    match file.file.read_exact_at(&mut block, handle.offset) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(corruption()),
+        Err(e) => return Err(Box::new(e)),
+    }
+
+    let checksum = block.split_off(handle.size as usize);
+
+    if crc32c::crc32c(&block).to_le_bytes()[..] != checksum[..] {
+        return Err(corruption());
+    }
+
+    compression.decompress(block)
+}
+
+/// Forces the directory entry of a just renamed file to the disk, so the rename survives a crash
+/// of the machine.
+fn sync_parent_directory(path: &str) -> Result<(), Box<dyn Error>> {
+    let parent = std::path::Path::new(path).parent();
+
+    if let Some(parent) = parent.filter(|parent| !parent.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,17 @@
+/// Deletion of all keys from `start` up to `end`, excluded, made by the write numbered
+/// `sequence`. It hides only the versions of the keys written before it.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct RangeTombstone<K> {
+    pub start: K,
+    pub end: K,
+    pub sequence: u64,
+}
+
+impl<K> RangeTombstone<K>
+where
+    K: Ord,
+{
+    pub fn covers(&self, key: &K) -> bool {
+        &self.start <= key && key < &self.end
+    }
+}
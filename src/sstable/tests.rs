@@ -1,5 +1,20 @@
-use crate::sstable::SsTable;
-use std::collections::BTreeMap;
+use crate::{
+    bloom_filter::BloomFilter,
+    sstable::{
+        BlockCache, BlockHandle, BloomFilterPolicy, Compression, Corruption, DataFile, FOOTER_SIZE,
+        FORMAT_VERSION, Layout, MAGIC, RangeTombstone, SsTable, SsTableOptions,
+        index::{BlockIndex, IndexEntry},
+        read_block, write_block,
+    },
+};
+use proptest::{collection, prelude::*};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
 
 #[test]
 fn test_existing_key_search() {
@@ -30,7 +45,7 @@ fn test_finds_key_after_last_index_item() {
 
     let key = "key_9999".to_string(); // Should be for sure one of the lasts
 
-    assert!(!table.block_index.contains_key(&key));
+    assert!(!full_index(&table).contains_key(&key));
 
     assert_eq!(table.get(&key).unwrap().unwrap(), "value_9999");
 }
@@ -38,14 +53,20 @@ fn test_finds_key_after_last_index_item() {
 #[test]
 fn test_missing_key_is_not_searched_in_db() {
     let table = ss_table("test_missing_key_is_not_searched_in_db");
-    std::fs::remove_file("target/test_missing_key_is_not_searched_in_db.data").unwrap();
+
+    // Wipe the data blocks in place, so reading any of them fails its checksum.
+    let path = "target/test_missing_key_is_not_searched_in_db.sst";
+    let mut data = std::fs::read(path).unwrap();
+    data[..table.properties().data_size as usize].fill(0);
+    std::fs::write(path, data).unwrap();
+    assert!(table.get(&"key_500".to_string()).is_err());
 
     // It's the matter of probability that we will not receive a false
     // positive from bloom filter, so a radom missing key is chosen that was
     // not found in bloom filter.
     let key = "key_500000".to_string();
 
-    assert!(!table.bloom_filter.contains(&key));
+    assert!(!table.bloom_filter.as_ref().unwrap().contains(&key));
 
     assert!(table.get(&key).unwrap().is_none());
 }
@@ -60,14 +81,1051 @@ fn test_iterator_consumption() {
     assert_eq!(map.get(&key).unwrap(), "value_500");
 }
 
+#[test]
+fn test_range_starts_from_requested_key() {
+    let table = ss_table("test_range_starts_from_requested_key");
+
+    let keys: Vec<_> = table
+        .range("key_5000".to_string()..="key_5010".to_string())
+        .unwrap()
+        .map(|pair| pair.unwrap().0)
+        .collect();
+
+    let mut expected: Vec<_> = (5000..=5010)
+        .map(|i| format!("key_{i}"))
+        .chain(std::iter::once("key_501".to_string()))
+        .collect();
+    expected.sort();
+
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_range_with_excluded_bounds() {
+    let table = ss_table("test_range_with_excluded_bounds");
+
+    let keys: Vec<_> = table
+        .range((
+            Bound::Excluded("key_9990".to_string()),
+            Bound::Excluded("key_9999".to_string()),
+        ))
+        .unwrap()
+        .map(|pair| pair.unwrap().0)
+        .collect();
+
+    let expected: Vec<_> = (9991..9999).map(|i| format!("key_{i}")).collect();
+
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_verify_accepts_intact_table() {
+    let table = ss_table("test_verify_accepts_intact_table");
+    table.verify().unwrap();
+}
+
+#[test]
+fn test_flipped_bit_in_block_is_reported_as_corruption() {
+    let name = "test_flipped_bit_in_block_is_reported_as_corruption";
+    let table = ss_table(name);
+
+    let key = "key_500".to_string();
+    let (_, entry) = full_index(&table)
+        .range(..=key.clone())
+        .next_back()
+        .unwrap();
+    let handle = entry.handle;
+
+    let path = format!("target/{name}.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[handle.offset as usize + 3] ^= 0x01;
+    std::fs::write(&path, data).unwrap();
+
+    let expected = Corruption {
+        file: path,
+        offset: handle.offset,
+    };
+
+    let error = table.get(&key).unwrap_err();
+    assert_eq!(error.downcast_ref::<Corruption>(), Some(&expected));
+
+    let error = table.verify().unwrap_err();
+    assert_eq!(error.downcast_ref::<Corruption>(), Some(&expected));
+
+    let error = table.iter().unwrap().find_map(Result::err).unwrap();
+    assert_eq!(error.downcast_ref::<Corruption>(), Some(&expected));
+}
+
+#[test]
+fn test_truncated_file_is_reported_as_corruption() {
+    let name = "test_truncated_file_is_reported_as_corruption";
+    let table = ss_table(name);
+
+    let (_, last_block) = full_index(&table).last_key_value().unwrap();
+
+    let path = format!("target/{name}.sst");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(last_block.handle.offset + 5)
+        .unwrap();
+
+    let error = table.verify().unwrap_err();
+    assert_eq!(error.downcast_ref::<Corruption>().unwrap().file, path);
+
+    let error = SsTable::<String, String>::load(format!("target/{name}"))
+        .err()
+        .unwrap();
+    assert_eq!(error.downcast_ref::<Corruption>().unwrap().file, path);
+}
+
+#[test]
+fn test_corrupted_meta_block_is_detected_on_load() {
+    let name = "test_corrupted_meta_block_is_detected_on_load";
+    let _ = ss_table(name);
+
+    let path = format!("target/{name}.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    let meta_index_byte = data.len() - FOOTER_SIZE as usize - 6;
+    data[meta_index_byte] ^= 0x10;
+    std::fs::write(&path, data).unwrap();
+
+    let error = SsTable::<String, String>::load(format!("target/{name}"))
+        .err()
+        .unwrap();
+
+    assert_eq!(error.downcast_ref::<Corruption>().unwrap().file, path);
+}
+
+#[test]
+fn test_load_checks_magic_and_version() {
+    let name = "test_load_checks_magic_and_version";
+    let _ = ss_table(name);
+
+    let path = format!("target/{name}.sst");
+    let original = std::fs::read(&path).unwrap();
+    let magic = original.len() - 8;
+
+    let mut data = original.clone();
+    data[magic - 4..magic].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    std::fs::write(&path, data).unwrap();
+
+    let error = SsTable::<String, String>::load(format!("target/{name}"))
+        .err()
+        .unwrap();
+    assert!(
+        error
+            .to_string()
+            .contains("unsupported SsTable format version")
+    );
+
+    let mut data = original;
+    data[magic..].copy_from_slice(&0_u64.to_le_bytes());
+    std::fs::write(&path, data).unwrap();
+
+    let error = SsTable::<String, String>::load(format!("target/{name}"))
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.downcast_ref::<Corruption>(),
+        Some(&Corruption {
+            file: path,
+            offset: magic as u64,
+        })
+    );
+}
+
+#[test]
+fn test_flipped_bits_in_footer_are_detected_on_load() {
+    let name = "test_flipped_bits_in_footer_are_detected_on_load";
+    let _ = ss_table(name);
+
+    let path = format!("target/{name}.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    let footer = data.len() - FOOTER_SIZE as usize;
+    // Top byte of the size of the meta-index block.
+    data[footer + 15] ^= 0x40;
+    std::fs::write(&path, data).unwrap();
+
+    let error = SsTable::<String, String>::load(format!("target/{name}"))
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.downcast_ref::<Corruption>(),
+        Some(&Corruption {
+            file: path,
+            offset: footer as u64,
+        })
+    );
+}
+
+#[test]
+fn test_handles_of_unchecksummed_footers_are_bounds_checked() {
+    let name = "test_handles_of_unchecksummed_footers_are_bounds_checked";
+
+    let path = format!("target/{name}.sst");
+    let original = include_bytes!("testdata/version_4.sst");
+    let footer = original.len() - FOOTER_SIZE as usize;
+    let meta_index_offset = u64::from_le_bytes(original[footer..footer + 8].try_into().unwrap());
+    let meta_index_size = u64::from_le_bytes(original[footer + 8..footer + 16].try_into().unwrap());
+
+    // Rewrite the footer as a version 3 one, without the checksum.
+    let version_3_table = |size: u64| {
+        let mut data = original[..footer].to_vec();
+        data.extend_from_slice(&meta_index_offset.to_le_bytes());
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(&3_u32.to_le_bytes());
+        data.extend_from_slice(&MAGIC.to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+    };
+
+    version_3_table(meta_index_size);
+    let table = SsTable::<String, String>::load(format!("target/{name}")).unwrap();
+    assert_eq!(
+        table.get(&"key_42".to_string()).unwrap(),
+        Some("value_42".to_string())
+    );
+
+    for size in [meta_index_size + 1, u64::MAX - 2] {
+        version_3_table(size);
+        let error = SsTable::<String, String>::load(format!("target/{name}"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<Corruption>(),
+            Some(&Corruption {
+                file: path.clone(),
+                offset: footer as u64,
+            })
+        );
+    }
+
+    let data_file = DataFile::open(path).unwrap();
+    for handle in [
+        BlockHandle {
+            offset: 0,
+            size: u64::MAX / 2,
+        },
+        BlockHandle {
+            offset: u64::MAX - 2,
+            size: 1,
+        },
+    ] {
+        let error = read_block(&data_file, &handle, Compression::None)
+            .err()
+            .unwrap();
+        assert!(error.downcast_ref::<Corruption>().is_some());
+    }
+}
+
+#[test]
+fn test_version_4_layout_is_readable() {
+    let name = "test_version_4_layout_is_readable";
+    let path = format!("target/{name}");
+
+    // Written by version 4 of `SsTable::new`: 100 pairs in blocks of 100 bytes, with keys sharing
+    // prefixes of their encodings.
+    std::fs::write(
+        format!("{path}.sst"),
+        include_bytes!("testdata/version_4.sst"),
+    )
+    .unwrap();
+
+    let expected: BTreeMap<_, _> = (0..100)
+        .map(|i| (format!("key_{i}"), format!("value_{i}")))
+        .collect();
+
+    let table = SsTable::<String, String>::load(path).unwrap();
+
+    assert_eq!(
+        table.get(&"key_42".to_string()).unwrap().unwrap(),
+        "value_42"
+    );
+    assert!(table.get(&"key_100".to_string()).unwrap().is_none());
+
+    let map: BTreeMap<_, _> = table.iter().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(map, expected);
+    table.verify().unwrap();
+}
+
+#[test]
+fn test_single_file_per_table() {
+    let name = "test_single_file_per_table";
+    for extension in ["sst", "data", "idx", "bloom"] {
+        let _ = std::fs::remove_file(format!("target/{name}.{extension}"));
+    }
+
+    let _ = ss_table(name);
+
+    for extension in ["data", "idx", "bloom"] {
+        assert!(!std::fs::exists(format!("target/{name}.{extension}")).unwrap());
+    }
+
+    SsTable::<String, String>::remove(&format!("target/{name}")).unwrap();
+    assert!(!std::fs::exists(format!("target/{name}.sst")).unwrap());
+}
+
+#[test]
+fn test_legacy_layout_is_readable() {
+    let name = "test_legacy_layout_is_readable";
+    let table = legacy_ss_table(name);
+
+    assert_eq!(table.layout, Layout::Legacy);
+    assert_eq!(
+        table.get(&"key_500".to_string()).unwrap().unwrap(),
+        "value_500"
+    );
+    assert!(table.get(&"key_50000".to_string()).unwrap().is_none());
+
+    let map: BTreeMap<_, _> = table.iter().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(map, test_data());
+
+    table.verify().unwrap();
+
+    SsTable::<String, String>::remove(&format!("target/{name}")).unwrap();
+    for extension in ["data", "idx", "bloom"] {
+        assert!(!std::fs::exists(format!("target/{name}.{extension}")).unwrap());
+    }
+}
+
+#[test]
+fn test_unchecksummed_legacy_layout_is_rewritten_on_load() {
+    let name = "test_unchecksummed_legacy_layout_is_rewritten_on_load";
+    let path = format!("target/{name}");
+    let _ = std::fs::remove_file(format!("{path}.sst"));
+
+    // Written by the first version of `SsTable::new`: 100 pairs in blocks of 10.
+    for (extension, bytes) in [
+        ("data", &include_bytes!("testdata/baseline.data")[..]),
+        ("idx", &include_bytes!("testdata/baseline.idx")[..]),
+        ("bloom", &include_bytes!("testdata/baseline.bloom")[..]),
+    ] {
+        std::fs::write(format!("{path}.{extension}"), bytes).unwrap();
+    }
+
+    let expected: BTreeMap<_, _> = (0..100)
+        .map(|i| (format!("key_{i:03}"), format!("value_{i}")))
+        .collect();
+
+    let table = SsTable::<String, String>::load(path.clone()).unwrap();
+
+    assert_eq!(table.layout, Layout::SingleFile);
+    assert_eq!(
+        table.get(&"key_042".to_string()).unwrap().unwrap(),
+        "value_42"
+    );
+    assert!(table.get(&"key_100".to_string()).unwrap().is_none());
+
+    let map: BTreeMap<_, _> = table.iter().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(map, expected);
+    table.verify().unwrap();
+
+    for extension in ["data", "idx", "bloom"] {
+        assert!(!std::fs::exists(format!("{path}.{extension}")).unwrap());
+    }
+
+    let table = SsTable::<String, String>::load(path).unwrap();
+    assert_eq!(table.iter().unwrap().count(), 100);
+}
+
+#[test]
+fn test_damaged_unchecksummed_legacy_layout_is_reported_as_corruption() {
+    let name = "test_damaged_unchecksummed_legacy_layout_is_reported_as_corruption";
+    let path = format!("target/{name}");
+    let _ = std::fs::remove_file(format!("{path}.sst"));
+
+    let mut data = include_bytes!("testdata/baseline.data").to_vec();
+    data.truncate(data.len() - 3);
+
+    std::fs::write(format!("{path}.data"), &data).unwrap();
+    std::fs::write(
+        format!("{path}.idx"),
+        include_bytes!("testdata/baseline.idx"),
+    )
+    .unwrap();
+    std::fs::write(
+        format!("{path}.bloom"),
+        include_bytes!("testdata/baseline.bloom"),
+    )
+    .unwrap();
+
+    let error = SsTable::<String, String>::load(path.clone()).err().unwrap();
+    let corruption = error.downcast_ref::<Corruption>().unwrap();
+    assert_eq!(corruption.file, format!("{path}.data"));
+
+    // Nothing is removed, so the table may still be recovered by hand.
+    assert!(std::fs::exists(format!("{path}.data")).unwrap());
+    assert!(!std::fs::exists(format!("{path}.sst")).unwrap());
+}
+
+#[test]
+fn test_bloom_filter_policy_is_stored_in_table() {
+    let policies = [
+        BloomFilterPolicy::Disabled,
+        BloomFilterPolicy::FalsePositiveRate(0.001),
+        BloomFilterPolicy::BitsPerKey(12),
+    ];
+
+    for policy in policies {
+        let name = "test_bloom_filter_policy_is_stored_in_table";
+        let options = SsTableOptions {
+            block_size: 10,
+            bloom_filter: policy,
+            ..Default::default()
+        };
+
+        let table = configured_ss_table(name, test_data(), &options);
+
+        assert_eq!(table.properties().bloom_filter, policy);
+        assert_eq!(
+            table.bloom_filter.is_some(),
+            policy != BloomFilterPolicy::Disabled
+        );
+
+        assert_eq!(
+            table.get(&"key_500".to_string()).unwrap().unwrap(),
+            "value_500"
+        );
+        assert!(table.get(&"key_50000".to_string()).unwrap().is_none());
+    }
+}
+
+#[test]
+fn test_lower_false_positive_rate_filters_more_missing_keys() {
+    let false_positives = |policy| {
+        let name = "test_lower_false_positive_rate_filters_more_missing_keys";
+        let options = SsTableOptions {
+            bloom_filter: policy,
+            ..Default::default()
+        };
+
+        let table = configured_ss_table(name, test_data(), &options);
+        let bloom_filter = table.bloom_filter.unwrap();
+
+        (10000..20000)
+            .filter(|i| bloom_filter.contains(&format!("key_{i}")))
+            .count()
+    };
+
+    let loose = false_positives(BloomFilterPolicy::FalsePositiveRate(0.1));
+    let strict = false_positives(BloomFilterPolicy::FalsePositiveRate(0.001));
+
+    assert!(loose > 500);
+    assert!(strict < 50);
+}
+
+#[test]
+fn test_false_positive_rates_which_are_no_probabilities_are_rejected() {
+    let name = "test_false_positive_rates_which_are_no_probabilities_are_rejected";
+
+    for rate in [0.0, 1.0, -0.5, 1.5, f64::NAN] {
+        let options = SsTableOptions {
+            bloom_filter: BloomFilterPolicy::FalsePositiveRate(rate),
+            ..Default::default()
+        };
+
+        let result =
+            SsTable::<String, String>::new(test_data(), &format!("target/{name}"), &options);
+
+        assert!(result.is_err(), "{rate}");
+    }
+}
+
+#[test]
+fn test_unavailable_codec_is_rejected() {
+    let codecs = [
+        (Compression::Lz4, cfg!(feature = "lz4")),
+        (Compression::Zstd, cfg!(feature = "zstd")),
+        (Compression::Snappy, cfg!(feature = "snappy")),
+    ];
+
+    for (compression, enabled) in codecs {
+        let options = SsTableOptions {
+            block_size: 10,
+            compression,
+            ..Default::default()
+        };
+
+        let result = SsTable::<String, String>::new(
+            test_data(),
+            "target/test_unavailable_codec_is_rejected",
+            &options,
+        );
+
+        assert_eq!(result.is_ok(), enabled);
+    }
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_lz4_compressed_table() {
+    compressed_table_roundtrip("test_lz4_compressed_table", Compression::Lz4);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_compressed_table() {
+    compressed_table_roundtrip("test_zstd_compressed_table", Compression::Zstd);
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy_compressed_table() {
+    compressed_table_roundtrip("test_snappy_compressed_table", Compression::Snappy);
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd", feature = "snappy"))]
+fn compressed_table_roundtrip(name: &str, compression: Compression) {
+    let options = SsTableOptions {
+        block_size: 100,
+        compression,
+        ..Default::default()
+    };
+
+    let uncompressed = configured_ss_table(
+        &format!("{name}_uncompressed"),
+        test_data(),
+        &SsTableOptions {
+            compression: Compression::None,
+            ..options.clone()
+        },
+    );
+
+    let table = configured_ss_table(name, test_data(), &options);
+
+    assert_eq!(table.properties().compression, compression);
+    assert!(table.size().unwrap() < uncompressed.size().unwrap());
+
+    assert_eq!(
+        table.get(&"key_500".to_string()).unwrap().unwrap(),
+        "value_500"
+    );
+    assert!(table.get(&"key_50000".to_string()).unwrap().is_none());
+
+    let map: BTreeMap<_, _> = table.iter().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(map, test_data());
+
+    table.verify().unwrap();
+}
+
+#[test]
+fn test_blocks_are_sized_in_bytes() {
+    let name = "test_blocks_are_sized_in_bytes";
+
+    // Mostly tiny values with a few much bigger than a block.
+    let data: BTreeMap<String, String> = (0..1000)
+        .map(|i| {
+            let size = if i % 100 == 0 { 10_000 } else { 10 };
+            (format!("key_{i:04}"), "x".repeat(size))
+        })
+        .collect();
+
+    let options = SsTableOptions {
+        block_bytes: 1024,
+        ..Default::default()
+    };
+    let table = configured_ss_table(name, data.clone(), &options);
+
+    let sizes: Vec<_> = full_index(&table)
+        .values()
+        .map(|entry| entry.handle.size)
+        .collect();
+
+    // A block is closed by the pair which reaches the target, so it's never much over it unless
+    // that pair is big on its own.
+    let big_blocks = sizes.iter().filter(|&&size| size > 1024 + 32).count();
+    assert_eq!(big_blocks, 10);
+    assert!(sizes.len() >= 20);
+
+    for (key, value) in &data {
+        assert_eq!(table.get(key).unwrap().as_ref(), Some(value));
+    }
+
+    table.verify().unwrap();
+}
+
+#[test]
+fn test_shared_key_prefixes_are_stored_once() {
+    let name = "test_shared_key_prefixes_are_stored_once";
+
+    let data: BTreeMap<String, String> = (0..1000)
+        .map(|i| (format!("tenant/42/object/{i:05}"), i.to_string()))
+        .collect();
+
+    let data_size = |restart_interval| {
+        let options = SsTableOptions {
+            restart_interval,
+            ..Default::default()
+        };
+        let table = configured_ss_table(
+            &format!("{name}_{restart_interval}"),
+            data.clone(),
+            &options,
+        );
+
+        table.properties().data_size
+    };
+
+    // With a restart point at every pair, every key is stored whole.
+    assert!(data_size(16) * 2 < data_size(1));
+}
+
+#[test]
+fn test_keys_of_different_lengths_share_prefixes() {
+    let name = "test_keys_of_different_lengths_share_prefixes";
+
+    let data_size = |data: BTreeMap<String, String>| {
+        let table = configured_ss_table(name, data, &SsTableOptions::default());
+
+        table.properties().data_size
+    };
+
+    let padded = data_size(
+        (0..1000)
+            .map(|i| (format!("tenant/42/object/{i:04}"), i.to_string()))
+            .collect(),
+    );
+    let unpadded = data_size(
+        (0..1000)
+            .map(|i| (format!("tenant/42/object/{i}"), i.to_string()))
+            .collect(),
+    );
+
+    // Unpadded keys are shorter, so they take no more room unless their lengths break sharing.
+    assert!(unpadded <= padded);
+}
+
+#[test]
+fn test_lookups_within_blocks_use_restart_points() {
+    let name = "test_lookups_within_blocks_use_restart_points";
+
+    let data: BTreeMap<String, String> = (0..1000)
+        .map(|i| (format!("key_{:04}", i * 2), format!("value_{i}")))
+        .collect();
+
+    for restart_interval in [1, 2, 7, 16, 1000] {
+        let options = SsTableOptions {
+            block_size: 100,
+            restart_interval,
+            ..Default::default()
+        };
+        let table = configured_ss_table(
+            &format!("{name}_{restart_interval}"),
+            data.clone(),
+            &options,
+        );
+
+        for i in 0..2000 {
+            let key = format!("key_{i:04}");
+            assert_eq!(table.get(&key).unwrap(), data.get(&key).cloned(), "{key}");
+        }
+
+        let from_missing_key: Vec<_> = table
+            .range("key_0151".to_string()..)
+            .unwrap()
+            .take(2)
+            .map(|pair| pair.unwrap().0)
+            .collect();
+        assert_eq!(from_missing_key, vec!["key_0152", "key_0154"]);
+
+        assert_eq!(
+            table
+                .range(("key_0400".to_string())..="key_0598".to_string())
+                .unwrap()
+                .count(),
+            100
+        );
+
+        table.verify().unwrap();
+    }
+}
+
+#[test]
+fn test_block_cache_serves_repeated_reads() {
+    let name = "test_block_cache_serves_repeated_reads";
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let table = ss_table(name).with_block_cache(0, block_cache.clone());
+    let key = "key_500".to_string();
+
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_500");
+    assert_eq!((block_cache.hits(), block_cache.misses()), (0, 1));
+
+    // The block is in memory now, so damaging the file doesn't affect the lookup.
+    let path = format!("target/{name}.sst");
+    let data_size = table.properties().data_size as usize;
+    let mut data = std::fs::read(&path).unwrap();
+    data[..data_size].fill(0);
+    std::fs::write(&path, data).unwrap();
+
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_500");
+    assert_eq!((block_cache.hits(), block_cache.misses()), (1, 1));
+}
+
+#[test]
+fn test_block_cache_is_shared_and_bounded() {
+    let block_cache = Arc::new(BlockCache::new(4 * 1024));
+    let first = ss_table("test_block_cache_is_shared_and_bounded_0")
+        .with_block_cache(0, block_cache.clone());
+    let second = ss_table("test_block_cache_is_shared_and_bounded_1")
+        .with_block_cache(1, block_cache.clone());
+
+    for (key, value) in test_data() {
+        assert_eq!(first.get(&key).unwrap().unwrap(), value);
+        assert_eq!(second.get(&key).unwrap().unwrap(), value);
+        assert!(block_cache.usage() <= 4 * 1024);
+    }
+
+    // Keys are read in order, so only the first key of every block of 10 misses the cache.
+    assert_eq!(block_cache.misses(), 2000);
+    assert_eq!(block_cache.hits(), 9 * 2000);
+}
+
+#[test]
+fn test_pinned_index_partitions_count_against_block_cache() {
+    let name = "test_pinned_index_partitions_count_against_block_cache";
+    let block_cache = Arc::new(BlockCache::new(64 * 1024));
+    let table = partitioned_ss_table(name)
+        .pin_index_partitions()
+        .with_block_cache(0, block_cache.clone());
+
+    for (key, value) in test_data() {
+        assert_eq!(table.get(&key).unwrap().unwrap(), value);
+        assert!(block_cache.usage() + block_cache.pinned_usage() <= 64 * 1024);
+    }
+
+    // Every partition is pinned once, and none of them is cached as a block.
+    let BlockIndex::Partitioned(partitions) = &table.block_index else {
+        panic!("index is not partitioned");
+    };
+    let pinned: u64 = partitions.values().map(|handle| handle.size).sum();
+    assert_eq!(block_cache.pinned_usage() as u64, pinned);
+
+    drop(table);
+    assert_eq!(block_cache.pinned_usage(), 0);
+}
+
+#[test]
+fn test_iterator_may_skip_filling_block_cache() {
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let table = ss_table("test_iterator_may_skip_filling_block_cache")
+        .with_block_cache(0, block_cache.clone());
+
+    let pairs = table.iter().unwrap().fill_cache(false).count();
+
+    assert_eq!(pairs, 10000);
+    assert_eq!(block_cache.usage(), 0);
+
+    table.iter().unwrap().count();
+
+    assert!(block_cache.usage() > 0);
+    assert_eq!(block_cache.hits(), 0);
+
+    table.iter().unwrap().count();
+
+    assert_eq!(block_cache.hits(), 1000);
+}
+
+#[test]
+fn test_new_table_replaces_stale_temporary_file() {
+    let path = "target/test_new_table_replaces_stale_temporary_file";
+
+    // Left behind by a process killed while writing the table.
+    std::fs::write(format!("{path}.sst.tmp"), b"torn table").unwrap();
+
+    let table = ss_table("test_new_table_replaces_stale_temporary_file");
+
+    assert!(!std::fs::exists(format!("{path}.sst.tmp")).unwrap());
+    assert_eq!(table.iter().unwrap().count(), 10000);
+}
+
+#[test]
+fn test_range_tombstones_are_stored_in_table() {
+    let path = "target/test_range_tombstones_are_stored_in_table";
+    let range_tombstones = vec![RangeTombstone {
+        start: "key_1".to_string(),
+        end: "key_2".to_string(),
+        sequence: 7,
+    }];
+
+    SsTable::<String, String>::with_range_tombstones(
+        test_data(),
+        range_tombstones.clone(),
+        path,
+        &SsTableOptions::default(),
+    )
+    .unwrap();
+
+    let table = SsTable::<String, String>::load(path.to_string()).unwrap();
+    assert_eq!(table.range_tombstones(), range_tombstones.as_slice());
+    assert!(table.range_tombstones()[0].covers(&"key_15".to_string()));
+    assert!(!table.range_tombstones()[0].covers(&"key_2".to_string()));
+    table.verify().unwrap();
+
+    assert!(
+        ss_table("test_tables_have_no_range_tombstones_by_default")
+            .range_tombstones()
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_partitioned_index_is_read_on_demand() {
+    let name = "test_partitioned_index_is_read_on_demand";
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let table = partitioned_ss_table(name).with_block_cache(0, block_cache.clone());
+
+    let BlockIndex::Partitioned(partitions) = &table.block_index else {
+        panic!("index is not partitioned");
+    };
+    assert!(partitions.len() > 10);
+
+    // A lookup reads one partition of the index and one data block.
+    let key = "key_500".to_string();
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_500");
+    assert_eq!((block_cache.hits(), block_cache.misses()), (0, 2));
+
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_500");
+    assert_eq!((block_cache.hits(), block_cache.misses()), (2, 2));
+
+    for (key, value) in test_data() {
+        assert_eq!(table.get(&key).unwrap().unwrap(), value);
+    }
+    assert!(table.get(&"a".to_string()).unwrap().is_none());
+    assert!(table.get(&"key_50000".to_string()).unwrap().is_none());
+
+    let expected: Vec<_> = test_data()
+        .range("key_5000".to_string().."key_6".to_string())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let pairs: Vec<_> = table
+        .range("key_5000".to_string().."key_6".to_string())
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(pairs, expected);
+
+    let pairs: Vec<_> = table.iter().unwrap().map(Result::unwrap).collect();
+    assert_eq!(pairs, test_data().into_iter().collect::<Vec<_>>());
+
+    table.verify().unwrap();
+}
+
+#[test]
+fn test_pinned_index_partitions_stay_in_memory() {
+    let name = "test_pinned_index_partitions_stay_in_memory";
+    let table = partitioned_ss_table(name).pin_index_partitions();
+    let key = "key_0".to_string();
+
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_0");
+
+    // Damages the first partition of the index, which the table has read already.
+    let BlockIndex::Partitioned(partitions) = &table.block_index else {
+        panic!("index is not partitioned");
+    };
+    let (_, partition) = partitions.first_key_value().unwrap();
+    let partition = *partition;
+
+    let path = format!("target/{name}.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[partition.offset as usize + 1] ^= 0x01;
+    std::fs::write(&path, data).unwrap();
+
+    assert_eq!(table.get(&key).unwrap().unwrap(), "value_0");
+
+    let unpinned = SsTable::<String, String>::load(format!("target/{name}")).unwrap();
+    let error = unpinned.get(&key).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Corruption>(),
+        Some(&Corruption {
+            file: path,
+            offset: partition.offset,
+        })
+    );
+    assert!(table.verify().is_err());
+}
+
+#[test]
+fn test_keys_between_blocks_are_not_searched_in_blocks() {
+    let name = "test_keys_between_blocks_are_not_searched_in_blocks";
+    let data: BTreeMap<_, _> = (0..100)
+        .map(|i| (format!("key_{:03}", i * 2), format!("value_{i}")))
+        .collect();
+    let options = SsTableOptions {
+        block_size: 10,
+        bloom_filter: BloomFilterPolicy::Disabled,
+        ..Default::default()
+    };
+
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let table = configured_ss_table(name, data, &options).with_block_cache(0, block_cache.clone());
+
+    // The first block ends with `key_018` and the second one starts with `key_020`.
+    let (_, entry) = full_index(&table).first_key_value().unwrap();
+    assert_eq!(entry.last_key.as_deref(), Some("key_018"));
+
+    assert!(table.get(&"key_019".to_string()).unwrap().is_none());
+    assert!(table.get(&"key_999".to_string()).unwrap().is_none());
+    assert_eq!(block_cache.misses(), 0);
+
+    assert!(table.get(&"key_017".to_string()).unwrap().is_none());
+    assert_eq!(block_cache.misses(), 1);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    /// Tables of any shape answer lookups and scans just like the map they are built from.
+    #[test]
+    fn test_table_matches_btree_map_model(
+        data in collection::btree_map(model_key(), collection::vec(any::<u8>(), 0..300), 0..200),
+        missing in collection::vec(model_key(), 0..50),
+        bounds in collection::vec((model_bound(), model_bound()), 0..10),
+        block_size in prop_oneof![Just(1), Just(usize::MAX), 1..20_usize],
+        block_bytes in prop_oneof![Just(1), 1..512_usize],
+        restart_interval in 1..8_usize,
+        index_partition_bytes in proptest::option::of(1..256_usize),
+    ) {
+        let path = "target/test_table_matches_btree_map_model";
+        let options = SsTableOptions {
+            block_size,
+            block_bytes,
+            restart_interval,
+            index_partition_bytes,
+            ..Default::default()
+        };
+
+        SsTable::<String, Vec<u8>>::new(data.clone(), path, &options).unwrap();
+        let table = SsTable::<String, Vec<u8>>::load(path.to_string()).unwrap();
+
+        table.verify().unwrap();
+
+        for (key, value) in &data {
+            prop_assert_eq!(table.get(key).unwrap(), Some(value.clone()));
+        }
+
+        for key in &missing {
+            prop_assert_eq!(table.get(key).unwrap(), data.get(key).cloned());
+        }
+
+        let pairs: Vec<_> = table.iter().unwrap().map(Result::unwrap).collect();
+        prop_assert_eq!(pairs, data.clone().into_iter().collect::<Vec<_>>());
+
+        for bounds in bounds {
+            let expected: Vec<_> = data
+                .iter()
+                .filter(|(key, _)| bounds.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            let pairs: Vec<_> = table.range(bounds).unwrap().map(Result::unwrap).collect();
+
+            prop_assert_eq!(pairs, expected);
+        }
+    }
+}
+
+/// Keys from a few letters, so they share prefixes and often fall between keys of a table.
+fn model_key() -> impl Strategy<Value = String> {
+    "[a-d]{0,6}"
+}
+
+fn model_bound() -> impl Strategy<Value = Bound<String>> {
+    prop_oneof![
+        Just(Bound::Unbounded),
+        model_key().prop_map(Bound::Included),
+        model_key().prop_map(Bound::Excluded),
+    ]
+}
+
 fn ss_table(name: &str) -> SsTable<String, String> {
+    let options = SsTableOptions {
+        block_size: 10,
+        ..Default::default()
+    };
+
+    configured_ss_table(name, test_data(), &options)
+}
+
+fn partitioned_ss_table(name: &str) -> SsTable<String, String> {
+    let options = SsTableOptions {
+        block_size: 10,
+        index_partition_bytes: Some(512),
+        ..Default::default()
+    };
+
+    configured_ss_table(name, test_data(), &options)
+}
+
+/// Writes `data` into the table of `name`, tuned by `options`, and loads it back.
+fn configured_ss_table(
+    name: &str,
+    data: BTreeMap<String, String>,
+    options: &SsTableOptions,
+) -> SsTable<String, String> {
+    SsTable::<String, String>::new(data, &format!("target/{name}"), options).unwrap();
+
+    SsTable::<String, String>::load(format!("target/{name}")).unwrap()
+}
+
+fn full_index(table: &SsTable<String, String>) -> &BTreeMap<String, IndexEntry<String>> {
+    match &table.block_index {
+        BlockIndex::Full(index) => index,
+        BlockIndex::Partitioned(_) => panic!("index is partitioned"),
+    }
+}
+
+/// Writes the test data in the checksummed encoding of the layout of three files used before
+/// single-file tables. Its first, unchecksummed encoding is covered by the files in `testdata`.
+fn legacy_ss_table(name: &str) -> SsTable<String, String> {
+    let path = format!("target/{name}");
+    let data: Vec<_> = test_data().into_iter().collect();
+
+    let mut data_writer = BufWriter::new(File::create(format!("{path}.data")).unwrap());
+    let mut bloom_filter = BloomFilter::new(data.len(), 0.1);
+    let mut block_index = BTreeMap::new();
+
+    for block_data in data.chunks(10) {
+        let mut block = Vec::new();
+
+        for (key, value) in block_data {
+            bloom_filter.add(key.clone());
+            bincode::encode_into_std_write((key, value), &mut block, bincode::config::standard())
+                .unwrap();
+        }
+
+        let handle = write_block(&mut data_writer, &block, Compression::None).unwrap();
+        block_index.insert(block_data[0].0.clone(), handle);
+    }
+
+    data_writer.flush().unwrap();
+
+    for (extension, serialized) in [
+        (
+            "idx",
+            bincode::encode_to_vec(
+                (Compression::None, &block_index),
+                bincode::config::standard(),
+            ),
+        ),
+        (
+            "bloom",
+            bincode::encode_to_vec(&bloom_filter, bincode::config::standard()),
+        ),
+    ] {
+        let mut serialized = serialized.unwrap();
+        serialized.extend(crc32c::crc32c(&serialized).to_le_bytes());
+        std::fs::write(format!("{path}.{extension}"), serialized).unwrap();
+    }
+
+    SsTable::<String, String>::load(path).unwrap()
+}
+
+fn test_data() -> BTreeMap<String, String> {
     let mut data = BTreeMap::new();
 
     for i in 0..10000 {
         data.insert(format!("key_{i}"), format!("value_{i}"));
     }
 
-    SsTable::<String, String>::new(data, format!("target/{name}").as_str(), 10).unwrap();
-
-    SsTable::<String, String>::load(format!("target/{name}")).unwrap()
+    data
 }
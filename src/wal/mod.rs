@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+};
+
+/// Size of a record header: CRC32C of the payload followed by the payload length.
+const HEADER_SIZE: usize = 8;
+
+/// Append-only log of checksummed records.
+///
+/// Every record is stored as `[crc32c: u32][length: u32][payload]`, where payload is the bincode
+/// representation of the record. A record which was only partially written (e.g. the process was
+/// killed in the middle of `append`) or whose checksum does not match is treated as the end of
+/// the log: it and everything after it are dropped when the log is opened.
+pub struct Wal<R> {
+    writer: BufWriter<File>,
+    _marker: PhantomData<R>,
+}
+
+impl<R> Wal<R>
+where
+    R: bincode::Encode + bincode::Decode<()>,
+{
+    /// Creates an empty log, discarding a previous one at the same path.
+    pub fn create(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            _marker: Default::default(),
+        })
+    }
+
+    /// Opens an existing log and returns it together with all records it holds. A torn tail is
+    /// cut off, so new records are appended right after the last valid one.
+    pub fn open(path: &str) -> Result<(Self, Vec<R>), Box<dyn Error>> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let (records, valid_len) = Self::read_records(&mut file)?;
+
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::Start(valid_len))?;
+
+        let wal = Self {
+            writer: BufWriter::new(file),
+            _marker: Default::default(),
+        };
+
+        Ok((wal, records))
+    }
+
+    /// Appends a record and hands it over to the OS, so it survives a crash of the process.
+    pub fn append(&mut self, record: &R) -> Result<(), Box<dyn Error>> {
+        let payload = bincode::encode_to_vec(record, bincode::config::standard())?;
+
+        self.writer
+            .write_all(&crc32c::crc32c(&payload).to_le_bytes())?;
+        self.writer
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Forces appended records to the disk, so they also survive a crash of the machine.
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Drops all records, e.g. once they are persisted somewhere else.
+    pub fn truncate(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+
+        let file = self.writer.get_mut();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(())
+    }
+
+    fn read_records(file: &mut File) -> Result<(Vec<R>, u64), Box<dyn Error>> {
+        let mut buf = Vec::new();
+        BufReader::new(file).read_to_end(&mut buf)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+
+        while let Some(header) = buf.get(pos..pos + HEADER_SIZE) {
+            let checksum = u32::from_le_bytes(header[0..4].try_into()?);
+            let len = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+
+            let payload_start = pos + HEADER_SIZE;
+            let Some(payload) = buf.get(payload_start..payload_start + len) else {
+                break;
+            };
+
+            if crc32c::crc32c(payload) != checksum {
+                break;
+            }
+
+            let (record, _) = bincode::decode_from_slice(payload, bincode::config::standard())?;
+            records.push(record);
+
+            pos = payload_start + len;
+        }
+
+        Ok((records, pos as u64))
+    }
+}
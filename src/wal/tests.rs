@@ -0,0 +1,83 @@
+use crate::wal::Wal;
+use std::{fs::OpenOptions, io::Write};
+
+#[test]
+fn test_appended_records_are_replayed() {
+    let path = "target/test_appended_records_are_replayed.wal";
+
+    let mut wal = Wal::<(String, u64)>::create(path).unwrap();
+
+    for i in 0..100 {
+        wal.append(&(format!("key_{i}"), i)).unwrap();
+    }
+
+    drop(wal);
+
+    let (_, records) = Wal::<(String, u64)>::open(path).unwrap();
+
+    assert_eq!(records.len(), 100);
+    assert_eq!(records[42], ("key_42".to_string(), 42));
+}
+
+#[test]
+fn test_torn_tail_is_dropped_and_log_stays_appendable() {
+    let path = "target/test_torn_tail_is_dropped_and_log_stays_appendable.wal";
+
+    let mut wal = Wal::<String>::create(path).unwrap();
+    wal.append(&"first".to_string()).unwrap();
+    wal.append(&"second".to_string()).unwrap();
+    drop(wal);
+
+    // Simulates a crash in the middle of writing the third record.
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(&[1, 2, 3, 4, 100, 0]).unwrap();
+    drop(file);
+
+    let (mut wal, records) = Wal::<String>::open(path).unwrap();
+    assert_eq!(records, vec!["first".to_string(), "second".to_string()]);
+
+    wal.append(&"third".to_string()).unwrap();
+    drop(wal);
+
+    let (_, records) = Wal::<String>::open(path).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            "first".to_string(),
+            "second".to_string(),
+            "third".to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_replay_stops_at_corrupted_record() {
+    let path = "target/test_replay_stops_at_corrupted_record.wal";
+
+    let mut wal = Wal::<String>::create(path).unwrap();
+    wal.append(&"first".to_string()).unwrap();
+    wal.append(&"second".to_string()).unwrap();
+    drop(wal);
+
+    let mut data = std::fs::read(path).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    std::fs::write(path, data).unwrap();
+
+    let (_, records) = Wal::<String>::open(path).unwrap();
+    assert_eq!(records, vec!["first".to_string()]);
+}
+
+#[test]
+fn test_truncate_drops_all_records() {
+    let path = "target/test_truncate_drops_all_records.wal";
+
+    let mut wal = Wal::<String>::create(path).unwrap();
+    wal.append(&"first".to_string()).unwrap();
+    wal.truncate().unwrap();
+    wal.append(&"second".to_string()).unwrap();
+    drop(wal);
+
+    let (_, records) = Wal::<String>::open(path).unwrap();
+    assert_eq!(records, vec!["second".to_string()]);
+}